@@ -0,0 +1,210 @@
+use crate::diagnostics::{Diagnostic, Severity, SourceFile};
+use crate::explorer;
+use crate::simulator::{ACCEPT_STATES, REJECT_STATES};
+use crate::transition_reader::span;
+use crate::transition_types::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// Looks for the mistakes that make a hand-written table halt somewhere it wasn't meant to.
+// The table still runs with any of them, so they are warnings, each pointing at the transitions involved
+pub struct Checker<'a> {
+    table : &'a TransitionTable,
+    source : &'a SourceFile,
+    states : Vec<&'a str>, //in the order they are first defined
+    transitions : HashMap<&'a str, Vec<&'a Transition>>,
+}
+
+fn is_halting(state: &str) -> bool {
+    ACCEPT_STATES.contains(&state) || REJECT_STATES.contains(&state)
+}
+
+// `'a'`, `'a' and 'b'` or `'a', 'b' and 'c'`
+fn list(symbols: &[&str]) -> String {
+    let quoted: Vec<String> = symbols.iter().map(|symbol| format!("'{}'", symbol)).collect();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => quoted.concat(),
+    }
+}
+
+// Names the tape on a multi-tape table
+fn on_tape(table: &TransitionTable, tape: usize) -> String {
+    if table.tapes() > 1 { format!(" on tape {}", tape + 1) } else { String::new() }
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(table: &'a TransitionTable, source: &'a SourceFile) -> Checker<'a> {
+        let mut states = Vec::new();
+        let mut transitions: HashMap<&str, Vec<&Transition>> = HashMap::new();
+        for transition in &table.transitions {
+            transitions.entry(&transition.state).or_insert_with(|| {
+                states.push(transition.state.as_str());
+                Vec::new()
+            }).push(transition);
+        }
+        Checker { table, source, states, transitions }
+    }
+
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = explorer::choices(self.table).iter()
+            .map(|choice| choice.diagnostic(self.source, Severity::Warning))
+            .collect();
+        diagnostics.extend(self.undefined_states());
+        let reachable = self.reachable();
+        diagnostics.extend(self.unreachable_states(&reachable));
+        diagnostics.extend(self.missing_cases(&reachable));
+        diagnostics.extend(self.never_written());
+        diagnostics.sort_by_key(|diagnostic| diagnostic.primary.span.start);
+        diagnostics
+    }
+
+    // States that are entered but have no transitions and aren't accept or reject
+    fn undefined_states(&self) -> Vec<Diagnostic> {
+        let mut targets: Vec<&str> = Vec::new();
+        let mut entries: HashMap<&str, Vec<&Transition>> = HashMap::new();
+        for transition in &self.table.transitions {
+            if self.transitions.contains_key(transition.next.as_str()) || is_halting(&transition.next) {
+                continue;
+            }
+            entries.entry(&transition.next).or_insert_with(|| {
+                targets.push(&transition.next);
+                Vec::new()
+            }).push(transition);
+        }
+
+        targets.iter().map(|target| {
+            let entries = &entries[target];
+            let message = format!("state '{}' has no transitions", target);
+            let mut diagnostic = Diagnostic::warning(&message, span(self.source, entries[0])).with_primary_label("entered here");
+            for entry in &entries[1..] {
+                diagnostic = diagnostic.with_label(span(self.source, entry), "also entered here");
+            }
+            diagnostic
+                .with_note("the machine halts there without accepting or rejecting")
+                .with_help("name it accept or reject if the machine is meant to stop there")
+        }).collect()
+    }
+
+    fn reachable(&self) -> HashSet<&'a str> {
+        let mut reachable = HashSet::from([self.table.start.as_str()]);
+        let mut pending = vec![self.table.start.as_str()];
+        while let Some(state) = pending.pop() {
+            for transition in self.transitions.get(state).into_iter().flatten() {
+                if reachable.insert(&transition.next) {
+                    pending.push(&transition.next);
+                }
+            }
+        }
+        reachable
+    }
+
+    fn unreachable_states(&self, reachable: &HashSet<&str>) -> Vec<Diagnostic> {
+        self.states.iter().filter(|state| !reachable.contains(*state)).map(|state| {
+            let transitions = &self.transitions[state];
+            let message = format!("state '{}' can't be reached from the start state '{}'", state, self.table.start);
+            Diagnostic::warning(&message, span(self.source, transitions[0]))
+                .with_primary_label("never taken")
+                .with_note(&format!("none of its {} transitions can ever be taken", transitions.len()))
+        }).collect()
+    }
+
+    // Symbols a reachable state has no transition for although the tape can hold them there
+    fn missing_cases(&self, reachable: &HashSet<&str>) -> Vec<Diagnostic> {
+        let mut alphabets = vec![BTreeSet::from([BLANK]); self.table.tapes()];
+        for transition in &self.table.transitions {
+            for (tape, alphabet) in alphabets.iter_mut().enumerate() {
+                alphabet.insert(transition.read[tape].as_str());
+                alphabet.insert(transition.write[tape].as_str());
+            }
+        }
+
+        let mut diagnostics = Vec::new();
+        for state in self.states.iter().filter(|state| reachable.contains(*state)) {
+            let transitions = &self.transitions[state];
+            for (tape, alphabet) in alphabets.iter().enumerate() {
+                let handled: HashSet<&str> = transitions.iter().map(|transition| transition.read[tape].as_str()).collect();
+                let missing: Vec<&str> = alphabet.iter().copied().filter(|symbol| !handled.contains(symbol)).collect();
+                if missing.is_empty() {
+                    continue;
+                }
+                let message = format!("state '{}' has no transition for {}{}", state, list(&missing), on_tape(self.table, tape));
+                diagnostics.push(Diagnostic::warning(&message, span(self.source, transitions[0]))
+                    .with_primary_label("missing cases")
+                    .with_note("the machine halts there without accepting or rejecting when it reads one of them")
+                    .with_help("add a transition into reject if that is on purpose"));
+            }
+        }
+        diagnostics
+    }
+
+    // Symbols that can only come from the input
+    fn never_written(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for tape in 0..self.table.tapes() {
+            let written: HashSet<&str> = self.table.transitions.iter().map(|transition| transition.write[tape].as_str()).collect();
+            let mut reported = HashSet::new();
+            for transition in &self.table.transitions {
+                let symbol = transition.read[tape].as_str();
+                if symbol == BLANK || written.contains(symbol) || !reported.insert(symbol) {
+                    continue;
+                }
+                let message = format!("'{}' is read{} but never written", symbol, on_tape(self.table, tape));
+                diagnostics.push(Diagnostic::warning(&message, span(self.source, transition))
+                    .with_primary_label("first read here")
+                    .with_note("it can only come from the initial tape"));
+            }
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition_reader::TableReader;
+
+    fn messages(table: &str) -> Vec<String> {
+        let source = SourceFile::new("test.tm", table);
+        let table = TableReader::new(table).read().unwrap();
+        Checker::new(&table, &source).check().into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn complete_tables_pass() {
+        let table = "
+            scan 0 0 right scan
+            scan 1 1 right scan
+            scan _ _ left  accept
+        ";
+        assert!(messages(table).is_empty());
+    }
+
+    #[test]
+    fn reports_each_problem_in_source_order() {
+        let table = "
+            start c b right start
+            start b b right start
+            start b a left  done
+            start a a right start
+            start _ _ stay  accept
+            lost  a a right start
+            lost  b b right start
+            lost  _ _ right start
+        ";
+        assert_eq!(messages(table), [
+            "'c' is read but never written",
+            "state 'start' has 2 transitions for 'b'",
+            "state 'done' has no transitions",
+            "state 'lost' can't be reached from the start state 'start'",
+        ]);
+    }
+
+    #[test]
+    fn reports_missing_cases_per_tape() {
+        let table = "
+            q a,_ a,x right,right q
+            q _,_ _,_ stay,stay   accept
+        ";
+        assert_eq!(messages(table), ["state 'q' has no transition for 'x' on tape 2"]);
+    }
+}
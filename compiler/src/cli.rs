@@ -0,0 +1,334 @@
+
+use crate::formats::Format;
+use crate::ir_types::{Encoding, Encodings};
+use crate::trace::{TraceFormat, TraceOptions};
+
+pub const USAGE: &str = "\
+usage: turing_compiler <command> [options] <input>...
+
+commands:
+    lex      write the tokens of each input as JSON
+    parse    write the syntax tree of each input as JSON
+    check    report every error in the inputs without writing anything, on a table also warn about
+             conflicting transitions, missing cases, unreachable or undefined states and symbols never written
+    build    compile an input down to a transition table
+    run      build an input and simulate it, a program also prints its variables
+    debug    build an input and step through it interactively with breakpoints and watchpoints
+    fmt      rewrite an input with normalised spacing and indentation
+
+inputs ending in .tm are transition tables in the README format, .jff files are JFLAP tables and anything
+else is a program. a k-tape table separates the k symbols and directions of a transition with commas:
+q0 a,_ a,x right,stay q1
+
+formats:
+    tm        the README format (default)
+    tmsim     turingmachinesimulator.com
+    morphett  morphett.info/turing, single tape only
+    jflap     JFLAP .jff files
+
+options:
+    -o, --output <path>    write the result to <path> instead of stdout, only with a single input
+    --emit <stage>         what build writes: tokens, ast, ir or tm (default)
+    --target <format>      the format build writes the table in
+    --from <format>        read every input as a table in <format>
+    --steps <dir>          also write every intermediate stage to <dir>/stepN.json
+    --encoding <encoding>  how compiled numbers sit on the tape: unary (default), binary-le, binary-be or decimal,
+                           <variable>=<encoding> sets it for one variable, can be repeated
+    --tape <symbols>       initial tape when running or debugging a table, one symbol per char,
+                           repeat it to fill the next tapes of a multi-tape table, the rest start blank
+    -O, --optimize         shrink the table before writing or running it and report its size after each pass
+    --single-tape          reduce a multi-tape table to a single tape one before writing or running it
+    --max-steps <n>        stop run after <n> steps (default 1000000)
+    --accelerate           have run move over a run of the same symbol in one step when a transition keeps its
+                           state, on a run length encoded tape, single tape deterministic tables only
+    --benchmark            have run print how long each input took and how many steps per second it ran
+                           instead of the tapes
+    --trace <path>         write every configuration run passes through to <path>: the step, state, heads,
+                           symbols read and written, moves and the cells around each head
+    --trace-format <format>
+                           text (default) or jsonl, one JSON object per line
+    --trace-states <states>
+                           only trace these comma separated states, can be repeated
+    --trace-every <n>      only trace every <n>th step
+    --trace-last <n>       only keep the last <n> traced configurations
+    --trace-window <n>     cells shown on each side of a head (default 5)
+    --strict               reject tables with more than one transition for the same state and symbols,
+                           without it run explores every choice breadth first and prints the accepting path
+    --max-configurations <n>
+                           stop exploring a nondeterministic table after <n> configurations (default 100000)
+    -h, --help             show this message
+
+exit codes:
+    0  success
+    1  the input has errors
+    2  bad command line
+    3  a file couldn't be read or written
+    4  run ended in a reject state
+    5  run hit --max-steps before the machine halted, or --max-configurations before a branch accepted";
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_INPUT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_IO: i32 = 3;
+pub const EXIT_REJECTED: i32 = 4;
+pub const EXIT_STEP_LIMIT: i32 = 5;
+
+const DEFAULT_MAX_STEPS: usize = 1_000_000;
+const DEFAULT_MAX_CONFIGURATIONS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Lex,
+    Parse,
+    Check,
+    Build,
+    Run,
+    Debug,
+    Fmt,
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Tm,
+}
+
+impl Emit {
+    fn parse(text: &str) -> Result<Emit, String> {
+        match text {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "ir" => Ok(Emit::Ir),
+            "tm" => Ok(Emit::Tm),
+            _ => Err(format!("unknown --emit stage '{}', expected tokens, ast, ir or tm", text)),
+        }
+    }
+}
+
+fn parse_format(text: &str) -> Result<Format, String> {
+    Format::parse(text).ok_or_else(|| format!("unknown format '{}', expected tm, tmsim, morphett or jflap", text))
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command : Command,
+    pub inputs : Vec<String>,
+    pub output : Option<String>,
+    pub emit : Emit,
+    pub target : Format,
+    pub from : Option<Format>,
+    pub steps : Option<String>,
+    pub encodings : Encodings,
+    pub tapes : Vec<String>,
+    pub single_tape : bool,
+    pub optimize : bool,
+    pub max_steps : usize,
+    pub accelerate : bool,
+    pub benchmark : bool,
+    pub trace : Option<String>,
+    pub trace_options : TraceOptions,
+    pub strict : bool,
+    pub max_configurations : usize,
+}
+
+impl Options {
+    // args excludes the program name
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut args = args.iter();
+        let command = match args.next().map(|s| s.as_str()) {
+            Some("lex") => Command::Lex,
+            Some("parse") => Command::Parse,
+            Some("check") => Command::Check,
+            Some("build") => Command::Build,
+            Some("run") => Command::Run,
+            Some("debug") => Command::Debug,
+            Some("fmt") => Command::Fmt,
+            Some("-h") | Some("--help") | Some("help") => Command::Help,
+            Some(other) => return Err(format!("unknown command '{}'", other)),
+            None => return Err("no command given".to_string()),
+        };
+
+        let mut options = Options {
+            command,
+            inputs: Vec::new(),
+            output: None,
+            emit: Emit::Tm,
+            target: Format::Tm,
+            from: None,
+            steps: None,
+            encodings: Encodings::default(),
+            tapes: Vec::new(),
+            single_tape: false,
+            optimize: false,
+            max_steps: DEFAULT_MAX_STEPS,
+            accelerate: false,
+            benchmark: false,
+            trace: None,
+            trace_options: TraceOptions::default(),
+            strict: false,
+            max_configurations: DEFAULT_MAX_CONFIGURATIONS,
+        };
+
+        while let Some(arg) = args.next() {
+            // both `--flag value` and `--flag=value` are accepted
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |name: &str| -> Result<String, String> {
+                match inline.clone() {
+                    Some(value) => Ok(value),
+                    None => args.next().cloned().ok_or_else(|| format!("{} needs a value", name)),
+                }
+            };
+
+            match flag {
+                "-o" | "--output" => options.output = Some(value(flag)?),
+                "--emit" => options.emit = Emit::parse(&value(flag)?)?,
+                "--target" => options.target = parse_format(&value(flag)?)?,
+                "--from" => options.from = Some(parse_format(&value(flag)?)?),
+                "--steps" => options.steps = Some(value(flag)?),
+                "--encoding" => {
+                    let text = value(flag)?;
+                    let (variable, name) = match text.split_once('=') {
+                        Some((variable, name)) => (Some(variable), name),
+                        None => (None, text.as_str()),
+                    };
+                    let encoding = Encoding::parse(name)
+                        .ok_or_else(|| format!("unknown encoding '{}', expected unary, binary-le, binary-be or decimal", name))?;
+                    match variable {
+                        Some(variable) => { options.encodings.variables.insert(variable.to_string(), encoding); }
+                        None => options.encodings.default = encoding,
+                    }
+                }
+                "--tape" => options.tapes.push(value(flag)?),
+                "--single-tape" => options.single_tape = true,
+                "-O" | "--optimize" => options.optimize = true,
+                "--max-steps" => {
+                    let text = value(flag)?;
+                    options.max_steps = text.parse().map_err(|_| format!("--max-steps expects a number, found '{}'", text))?;
+                }
+                "--accelerate" => options.accelerate = true,
+                "--benchmark" => options.benchmark = true,
+                "--trace" => options.trace = Some(value(flag)?),
+                "--trace-format" => {
+                    let text = value(flag)?;
+                    options.trace_options.format = TraceFormat::parse(&text)
+                        .ok_or_else(|| format!("unknown trace format '{}', expected text or jsonl", text))?;
+                }
+                "--trace-states" => options.trace_options.states.extend(value(flag)?.split(',').map(|state| state.trim().to_string())),
+                "--trace-every" => {
+                    let text = value(flag)?;
+                    options.trace_options.every = text.parse().ok().filter(|every| *every > 0)
+                        .ok_or_else(|| format!("--trace-every expects a positive number, found '{}'", text))?;
+                }
+                "--trace-last" => {
+                    let text = value(flag)?;
+                    options.trace_options.last = Some(text.parse().map_err(|_| format!("--trace-last expects a number, found '{}'", text))?);
+                }
+                "--trace-window" => {
+                    let text = value(flag)?;
+                    options.trace_options.window = text.parse().map_err(|_| format!("--trace-window expects a number, found '{}'", text))?;
+                }
+                "--strict" => options.strict = true,
+                "--max-configurations" => {
+                    let text = value(flag)?;
+                    options.max_configurations = text.parse().map_err(|_| format!("--max-configurations expects a number, found '{}'", text))?;
+                }
+                "-h" | "--help" => options.command = Command::Help,
+                _ if flag.starts_with('-') && flag.len() > 1 => return Err(format!("unknown option '{}'", flag)),
+                _ => options.inputs.push(arg.clone()),
+            }
+        }
+
+        if options.command == Command::Help {
+            return Ok(options);
+        }
+        if options.inputs.is_empty() {
+            return Err("no input file given".to_string());
+        }
+        if options.output.is_some() && options.inputs.len() > 1 {
+            return Err("-o can only be used with a single input".to_string());
+        }
+        if options.steps.is_some() && options.inputs.len() > 1 {
+            return Err("--steps can only be used with a single input".to_string());
+        }
+        if options.command == Command::Debug && options.inputs.len() > 1 {
+            return Err("debug takes a single input".to_string());
+        }
+        if options.benchmark && options.command != Command::Run {
+            return Err("--benchmark can only be used with run".to_string());
+        }
+        if options.accelerate && options.command != Command::Run {
+            return Err("--accelerate can only be used with run".to_string());
+        }
+        if options.accelerate && options.trace.is_some() {
+            return Err("--accelerate skips the configurations --trace would write".to_string());
+        }
+        // writing the trace would be most of what gets timed
+        if options.benchmark && options.trace.is_some() {
+            return Err("--benchmark and --trace can't be used together".to_string());
+        }
+        if options.trace.is_some() && (options.command != Command::Run || options.inputs.len() > 1) {
+            return Err("--trace can only be used with run on a single input".to_string());
+        }
+        if options.trace.is_none() && options.trace_options != TraceOptions::default() {
+            return Err("the --trace-* options need --trace".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(&args.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn reads_values_after_or_inside_flags() {
+        let spaced = parse("build in.txt -o out.tm --emit ir --encoding x=binary-le --max-steps 7 --target tmsim").unwrap();
+        let inline = parse("build in.txt --output=out.tm --emit=ir --encoding=x=binary-le --max-steps=7 --target=tmsim").unwrap();
+        for options in [&spaced, &inline] {
+            assert_eq!(options.command, Command::Build);
+            assert_eq!(options.inputs, ["in.txt"]);
+            assert_eq!(options.output.as_deref(), Some("out.tm"));
+            assert_eq!(options.emit, Emit::Ir);
+            assert_eq!(options.encodings.variables["x"], Encoding::BinaryLittleEndian);
+            assert_eq!(options.max_steps, 7);
+            assert_eq!(options.target, Format::TuringMachineSimulator);
+        }
+        let defaults = parse("run a.txt b.txt").unwrap();
+        assert_eq!((defaults.inputs.len(), defaults.emit, defaults.max_steps), (2, Emit::Tm, DEFAULT_MAX_STEPS));
+        assert_eq!(parse("--help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn rejects_bad_command_lines() {
+        let error = |args: &str| parse(args).unwrap_err();
+        assert_eq!(error(""), "no command given");
+        assert_eq!(error("compile a.txt"), "unknown command 'compile'");
+        assert_eq!(error("build a.txt --fast"), "unknown option '--fast'");
+        assert_eq!(error("build a.txt -o"), "-o needs a value");
+        assert_eq!(error("build a.txt --emit=bytes"), "unknown --emit stage 'bytes', expected tokens, ast, ir or tm");
+        assert_eq!(error("build a.txt --target xml"), "unknown format 'xml', expected tm, tmsim, morphett or jflap");
+        assert_eq!(error("build a.txt --encoding roman"), "unknown encoding 'roman', expected unary, binary-le, binary-be or decimal");
+        assert_eq!(error("run a.txt --max-steps=lots"), "--max-steps expects a number, found 'lots'");
+        assert_eq!(error("run a.txt --trace t --trace-every 0"), "--trace-every expects a positive number, found '0'");
+        assert_eq!(error("build"), "no input file given");
+        assert_eq!(error("build a.txt b.txt -o out"), "-o can only be used with a single input");
+        assert_eq!(error("build a.txt b.txt --steps dir"), "--steps can only be used with a single input");
+        assert_eq!(error("debug a.txt b.txt"), "debug takes a single input");
+        assert_eq!(error("build a.txt --benchmark"), "--benchmark can only be used with run");
+        assert_eq!(error("build a.txt --accelerate"), "--accelerate can only be used with run");
+        assert_eq!(error("run a.txt --accelerate --trace t"), "--accelerate skips the configurations --trace would write");
+        assert_eq!(error("run a.txt --benchmark --trace t"), "--benchmark and --trace can't be used together");
+        assert_eq!(error("build a.txt --trace t"), "--trace can only be used with run on a single input");
+        assert_eq!(error("run a.txt --trace-last 3"), "the --trace-* options need --trace");
+    }
+}
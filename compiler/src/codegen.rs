@@ -0,0 +1,177 @@
+use crate::codegen_types::*;
+use crate::ir_types::*;
+use crate::simulator::Tape;
+use crate::submachines::{self, SubMachine};
+use crate::transition_types::*;
+
+// Turns the IR into a single tape machine by wiring together one sub-machine per instruction.
+//
+// Every region holds the digits of its value after a marker, so in unary `let a = 2;` starts out as `##11#`, an
+// empty region for a and one holding the literal, and in little endian binary as `##01#`. Each block starts in a
+// state named after it (`bb3`) and the states of its instructions are prefixed with the block and their index
+// (`bb3.0.home`), so every name is unique and the same program always gives the same table.
+pub fn generate(program: &IrProgram) -> TransitionTable {
+    let encodings: Vec<Encoding> = program.layout.regions.iter().map(|region| region.encoding).collect();
+    let alphabet = Alphabet::new(&encodings);
+    let mut machine = SubMachine::new("program");
+    for block in &program.blocks {
+        wire_block(&mut machine, &alphabet, &program.layout, block);
+    }
+
+    let mut cells = vec![MARKER];
+    for region in &program.layout.regions {
+        if let RegionKind::Constant(value) = region.kind {
+            cells.extend(encode(region.encoding, value));
+        }
+        cells.push(MARKER);
+    }
+    machine.then("init", &submachines::write(&alphabet, &cells), START_STATE, &BlockId(0).to_string());
+
+    TransitionTable { start: START_STATE.to_string(), transitions: machine.transitions }
+}
+
+fn wire_block(machine: &mut SubMachine, alphabet: &Alphabet, layout: &Layout, block: &BasicBlock) {
+    let target = match block.terminator {
+        Terminator::Jump(target) => target.to_string(),
+        Terminator::Halt => ACCEPT_STATE.to_string(),
+        Terminator::BranchZero { .. } => format!("{}.branch", block.id),
+    };
+
+    // the instructions run one after the other and the last one leads into the terminator
+    let mut state = block.id.to_string();
+    for (i, instruction) in block.instructions.iter().enumerate() {
+        let next = if i + 1 == block.instructions.len() { target.clone() } else { format!("{}.{}", block.id, i + 1) };
+        machine.then(&format!("{}.{}", block.id, i), &submachine(alphabet, layout, instruction), &state, &next);
+        state = next;
+    }
+
+    match block.terminator {
+        Terminator::BranchZero { zero, nonzero, .. } => {
+            let exits = [(ZERO, zero.to_string()), (NONZERO, nonzero.to_string())];
+            let exits: Vec<(&str, &str)> = exits.iter().map(|(exit, target)| (*exit, target.as_str())).collect();
+            machine.include(&format!("{}.branch", block.id), &submachines::test_zero(alphabet), &state, &exits);
+        }
+        _ if block.instructions.is_empty() => machine.then(&format!("{}.goto", block.id), &submachines::goto(), &state, &target),
+        _ => {}
+    }
+}
+
+fn submachine(alphabet: &Alphabet, layout: &Layout, instruction: &Instruction) -> SubMachine {
+    let encoding = |region: RegionId| layout.get(region).encoding;
+    match *instruction {
+        Instruction::MoveTo(region) => submachines::seek(alphabet, region.0),
+        Instruction::Increment(region) => submachines::increment(alphabet, encoding(region), region.0),
+        Instruction::Decrement(region) => submachines::decrement(alphabet, encoding(region)),
+        Instruction::Clear(_) => submachines::clear(alphabet),
+        Instruction::Copy { from, to } => submachines::copy(alphabet, encoding(from), from.0, to.0),
+    }
+}
+
+// The digits of a value, most significant first unless the encoding is little endian
+pub fn encode(encoding: Encoding, value: usize) -> Vec<&'static str> {
+    let digits = digits(encoding);
+    if encoding == Encoding::Unary {
+        return vec![digits[0]; value];
+    }
+    let mut cells = Vec::new();
+    let mut rest = value;
+    while rest > 0 {
+        cells.push(digits[rest % digits.len()]);
+        rest /= digits.len();
+    }
+    if encoding != Encoding::BinaryLittleEndian {
+        cells.reverse();
+    }
+    cells
+}
+
+fn decode_cells(encoding: Encoding, cells: &[&str]) -> usize {
+    let digits = digits(encoding);
+    let value = |cell: &&str| digits.iter().position(|digit| digit == cell).unwrap_or(0);
+    match encoding {
+        Encoding::Unary => cells.iter().filter(|cell| digits.contains(cell)).count(),
+        Encoding::BinaryLittleEndian => cells.iter().rev().fold(0, |total, cell| total * digits.len() + value(cell)),
+        _ => cells.iter().fold(0, |total, cell| total * digits.len() + value(cell)),
+    }
+}
+
+// Reads every variable back off a final tape
+pub fn decode(layout: &Layout, tape: &Tape) -> Vec<(String, usize)> {
+    // everything from the first marker on, split at the markers
+    let symbols = tape.symbols();
+    let start = symbols.iter().position(|symbol| *symbol == MARKER).unwrap_or(symbols.len());
+    let cells: Vec<&[&str]> = symbols[start..].split(|symbol| *symbol == MARKER).skip(1).collect();
+
+    layout.regions.iter().zip(cells)
+        .filter(|(region, _)| region.kind == RegionKind::Variable)
+        .map(|(region, cells)| (region.name.clone(), decode_cells(region.encoding, cells)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::lowering::Lowering;
+    use crate::parser::Parser;
+    use crate::simulator::{Halt, Machine};
+
+    // Compiles a program, runs it on a blank tape and reads its variables back
+    fn run(source: &str, encoding: Encoding) -> Vec<(String, usize)> {
+        let (program, errors) = Parser::new(Lexer::new(source).tokenize().unwrap()).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let encodings = Encodings { default: encoding, ..Encodings::default() };
+        let ir = Lowering::new(&encodings).lower(&program).unwrap();
+        let report = Machine::new(&generate(&ir), vec![Tape::new()]).run(50_000_000);
+        assert_eq!(report.halt, Some(Halt::Accept));
+        decode(&ir.layout, &report.tapes[0])
+    }
+
+    fn values(pairs: &[(&str, usize)]) -> Vec<(String, usize)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+    }
+
+    const ENCODINGS: [Encoding; 4] = [Encoding::Unary, Encoding::BinaryLittleEndian, Encoding::BinaryBigEndian, Encoding::Decimal];
+
+    #[test]
+    fn encodes_and_decodes_values() {
+        assert_eq!(encode(Encoding::Unary, 3), ["1", "1", "1"]);
+        assert_eq!(encode(Encoding::BinaryLittleEndian, 6), ["0", "1", "1"]);
+        assert_eq!(encode(Encoding::BinaryBigEndian, 6), ["1", "1", "0"]);
+        assert!(encode(Encoding::Decimal, 0).is_empty());
+        for encoding in ENCODINGS {
+            for value in [0, 1, 7, 12, 130] {
+                assert_eq!(decode_cells(encoding, &encode(encoding, value)), value, "{:?}", encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn runs_arithmetic() {
+        // subtraction stops at zero
+        let source = "let a = 5; let b = a + 3; let c = b - a * 2; let d = b / 3; a++; b -= 2;";
+        for encoding in ENCODINGS {
+            assert_eq!(run(source, encoding), values(&[("a", 6), ("b", 6), ("c", 0), ("d", 2)]), "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn runs_branches_and_loops() {
+        let source = "
+            let n = 4;
+            let f = 1;
+            while n > 0 {
+                f = f * n;
+                n--;
+            }
+            let big = 0;
+            if f >= 20 and not f == 25 {
+                big = 1;
+            } else {
+                big = 2;
+            }
+        ";
+        assert_eq!(run(source, Encoding::BinaryLittleEndian), values(&[("n", 0), ("f", 24), ("big", 1)]));
+        assert_eq!(run(source, Encoding::Decimal), values(&[("n", 0), ("f", 24), ("big", 1)]));
+    }
+}
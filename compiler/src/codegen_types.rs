@@ -0,0 +1,56 @@
+use crate::ir_types::Encoding;
+
+// Tape symbol that starts every region, the region's digits follow it
+pub const MARKER: &str = "#";
+pub const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+// MARKS[d] stands in for digit d once copy has copied it
+pub const MARKS: [&str; 10] = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+// Marks a cell that is being removed
+pub const DELETED: &str = "x";
+
+pub const START_STATE: &str = "start";
+pub const ACCEPT_STATE: &str = "accept";
+
+// Every sub-machine starts in ENTRY and leaves through one of these
+pub const ENTRY: &str = "entry";
+pub const DONE: &str = "@done";
+pub const ZERO: &str = "@zero";
+pub const NONZERO: &str = "@nonzero";
+
+// The digits an encoding writes, a value never has leading zeros so 0 is an empty region
+pub fn digits(encoding: Encoding) -> &'static [&'static str] {
+    match encoding {
+        Encoding::Unary => &DIGITS[1..2],
+        Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => &DIGITS[..2],
+        Encoding::Decimal => &DIGITS,
+    }
+}
+
+// Every symbol a machine can meet between the first and the last marker. Sub-machines only handle the
+// digits that are actually used, since moving cells around takes a state per symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alphabet {
+    pub digits : Vec<&'static str>,
+}
+
+impl Alphabet {
+    pub fn new(encodings: &[Encoding]) -> Alphabet {
+        let digits = DIGITS.iter()
+            .filter(|digit| encodings.iter().any(|&encoding| digits(encoding).contains(digit)))
+            .copied()
+            .collect();
+        Alphabet { digits }
+    }
+
+    pub fn mark(digit: &str) -> &'static str {
+        MARKS[DIGITS.iter().position(|d| *d == digit).expect("only digits are marked")]
+    }
+
+    // The markers, digits and marked digits, DELETED only ever exists inside submachines::delete
+    pub fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = vec![MARKER];
+        symbols.extend(&self.digits);
+        symbols.extend(self.digits.iter().map(|digit| Alphabet::mark(digit)));
+        symbols
+    }
+}
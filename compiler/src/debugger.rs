@@ -0,0 +1,315 @@
+use crate::codegen;
+use crate::ir_types::Layout;
+use crate::simulator::{Machine, Tape};
+use crate::transition_types::*;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+    step [n]                 take n steps (default 1), stopping early at a breakpoint or watchpoint
+    continue                 run until a breakpoint, a watchpoint or a halt, at most --max-steps steps
+    back [n]                 go back n steps (default 1)
+    jump <step>              go back or forward to a step
+    break <state> [symbols]  stop when the machine enters <state>, or only when it reads <symbols> there,
+                             comma separated on a multi-tape table
+    watch [tape:]<cell>      stop when the symbol in <cell> changes, the input starts at cell 0 of tape 1
+    delete <n>               remove breakpoint or watchpoint <n>
+    info                     list the breakpoints and watchpoints
+    print [state|tape|variables]
+                             show where the machine is, its tapes, or the variables of a compiled program
+    help                     show this message
+    quit                     leave the debugger
+
+an empty line repeats the last command, the first letter of a command other than back is enough";
+
+// Where continue and step stop
+enum Point {
+    State(String),
+    Read(String, Vec<String>),
+    Watch { tape: usize, cell: i64, symbol: String }, //symbol is what the cell held when last looked at
+}
+
+impl Point {
+    fn describe(&self, id: usize) -> String {
+        match self {
+            Point::State(state) => format!("breakpoint {} in state {}", id, state),
+            Point::Read(state, symbols) => format!("breakpoint {} in state {} reading {}", id, state, symbols.join(",")),
+            Point::Watch { tape, cell, .. } => format!("watchpoint {} on cell {} of tape {}", id, cell, tape + 1),
+        }
+    }
+}
+
+// The non-blank part of a tape and the head, with the head's cell in brackets
+fn show(tape: &Tape) -> String {
+    let (lowest, highest) = tape.bounds().unwrap_or((tape.head(), tape.head()));
+    let (lowest, highest) = (lowest.min(tape.head()), highest.max(tape.head()));
+    let cells: Vec<String> = (lowest..=highest)
+        .map(|index| if index == tape.head() { format!("[{}]", tape.get(index)) } else { tape.get(index).to_string() })
+        .collect();
+    format!("{} (from cell {})", cells.join(" "), lowest)
+}
+
+// A REPL over a Machine, on a nondeterministic table it follows the first choice
+pub struct Debugger {
+    machine : Machine,
+    layout : Option<Layout>, //only for compiled programs
+    points : Vec<(usize, Point)>,
+    count : usize, //points ever set, so numbers aren't reused after delete
+    max_steps : usize,
+    last : String,
+}
+
+impl Debugger {
+    pub fn new(table: &TransitionTable, tapes: Vec<Tape>, layout: Option<Layout>, max_steps: usize) -> Debugger {
+        let mut machine = Machine::new(table, tapes);
+        machine.keep_history();
+        Debugger { machine, layout, points: Vec::new(), count: 0, max_steps, last: String::new() }
+    }
+
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.location())?;
+        write!(out, "(debug) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.execute(&line?, &mut out)? {
+                return Ok(());
+            }
+            write!(out, "(debug) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    // Runs one command line, false once the user quits
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = if line.trim().is_empty() { self.last.clone() } else { line.trim().to_string() };
+        self.last = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            return Ok(true);
+        };
+
+        match (command, arguments) {
+            ("s" | "step", []) => self.advance(1, out)?,
+            ("s" | "step", [count]) => match count.parse() {
+                Ok(count) => self.advance(count, out)?,
+                Err(_) => writeln!(out, "step expects a number of steps, found '{}'", count)?,
+            },
+            ("c" | "continue", []) => self.advance(self.max_steps, out)?,
+            ("back", []) => self.travel(|machine| machine.step_back(1), out)?,
+            ("back", [count]) => match count.parse() {
+                Ok(count) => self.travel(|machine| machine.step_back(count), out)?,
+                Err(_) => writeln!(out, "back expects a number of steps, found '{}'", count)?,
+            },
+            ("j" | "jump", [step]) => match step.parse() {
+                Ok(step) => self.travel(|machine| machine.jump(step), out)?,
+                Err(_) => writeln!(out, "jump expects a step number, found '{}'", step)?,
+            },
+            ("b" | "break", [state]) => self.add(Point::State(state.to_string()), out)?,
+            ("b" | "break", [state, symbols]) => {
+                let symbols: Vec<String> = symbols.split(',').map(str::to_string).collect();
+                let tapes = self.machine.tape_count();
+                if symbols.len() == tapes {
+                    self.add(Point::Read(state.to_string(), symbols), out)?;
+                } else {
+                    writeln!(out, "the machine reads {} symbols at once, found {}", tapes, symbols.len())?;
+                }
+            }
+            ("w" | "watch", [cell]) => {
+                let (tape, cell) = match cell.split_once(':') {
+                    Some((tape, cell)) => (tape.parse::<usize>().ok().filter(|tape| *tape > 0).map(|tape| tape - 1), cell),
+                    None => (Some(0), *cell),
+                };
+                match (tape.filter(|tape| *tape < self.machine.tape_count()), cell.parse::<i64>()) {
+                    (Some(tape), Ok(cell)) => {
+                        let symbol = self.machine.cell(tape, cell).to_string();
+                        self.add(Point::Watch { tape, cell, symbol }, out)?;
+                    }
+                    _ => writeln!(out, "expected a cell like 3 or 2:-1 on a machine with {} tapes", self.machine.tape_count())?,
+                }
+            }
+            ("d" | "delete", [number]) => {
+                let found = self.points.iter().position(|(id, _)| id.to_string() == *number);
+                match found {
+                    Some(index) => {
+                        let (id, point) = self.points.remove(index);
+                        writeln!(out, "deleted {}", point.describe(id))?;
+                    }
+                    None => writeln!(out, "there is no breakpoint or watchpoint {}", number)?,
+                }
+            }
+            ("i" | "info", []) if self.points.is_empty() => writeln!(out, "no breakpoints or watchpoints")?,
+            ("i" | "info", []) => {
+                for (id, point) in &self.points {
+                    writeln!(out, "{}", point.describe(*id))?;
+                }
+            }
+            ("p" | "print", []) => {
+                writeln!(out, "{}", self.location())?;
+                self.print_tapes(out)?;
+            }
+            ("p" | "print", ["state"]) => writeln!(out, "{}", self.location())?,
+            ("p" | "print", ["tape"]) => self.print_tapes(out)?,
+            ("p" | "print", ["variables"]) => match &self.layout {
+                Some(layout) => {
+                    for (name, value) in codegen::decode(layout, &self.machine.tapes()[0]) {
+                        writeln!(out, "{} = {}", name, value)?;
+                    }
+                }
+                None => writeln!(out, "only compiled programs have variables")?,
+            },
+            ("h" | "help", []) => writeln!(out, "{}", HELP)?,
+            ("q" | "quit", []) => return Ok(false),
+            _ => writeln!(out, "can't make sense of '{}', type help for the commands", line)?,
+        }
+        Ok(true)
+    }
+
+    fn add(&mut self, point: Point, out: &mut impl Write) -> io::Result<()> {
+        self.count += 1;
+        writeln!(out, "{}", point.describe(self.count))?;
+        self.points.push((self.count, point));
+        Ok(())
+    }
+
+    // `step 12 in carry reading 1, next: carry 1 0 left carry`
+    fn location(&self) -> String {
+        let read: Vec<&str> = (0..self.machine.tape_count()).map(|tape| self.machine.read(tape)).collect();
+        let next = match (self.machine.next_transition(), self.machine.halted()) {
+            (Some(transition), _) => format!("next: {}", transition),
+            (None, Some(halt)) => format!("halted ({:?})", halt),
+            (None, None) => "halted".to_string(),
+        };
+        format!("step {} in {} reading {}, {}", self.machine.steps(), self.machine.state(), read.join(","), next)
+    }
+
+    fn print_tapes(&self, out: &mut impl Write) -> io::Result<()> {
+        match self.machine.tapes().as_slice() {
+            [tape] => writeln!(out, "tape: {}", show(tape)),
+            tapes => {
+                for (index, tape) in tapes.iter().enumerate() {
+                    writeln!(out, "tape {}: {}", index + 1, show(tape))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // After back or jump the watchpoints compare against the cells as they are now, breakpoints don't stop either
+    fn travel(&mut self, go: impl FnOnce(&mut Machine) -> Result<(), String>, out: &mut impl Write) -> io::Result<()> {
+        if let Err(message) = go(&mut self.machine) {
+            return writeln!(out, "{}", message);
+        }
+        for (_, point) in &mut self.points {
+            if let Point::Watch { tape, cell, symbol } = point {
+                *symbol = self.machine.cell(*tape, *cell).to_string();
+            }
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    // Takes up to count steps, after each one checking every point so all watchpoints stay up to date
+    fn advance(&mut self, count: usize, out: &mut impl Write) -> io::Result<()> {
+        if self.machine.halted().is_some() {
+            return writeln!(out, "the machine has halted, {}", self.location());
+        }
+        for _ in 0..count {
+            self.machine.step();
+            let mut stopped = false;
+            for (id, point) in &mut self.points {
+                let hit = match point {
+                    Point::State(state) => self.machine.state() == state,
+                    Point::Read(state, symbols) => {
+                        self.machine.state() == state && (0..symbols.len()).all(|tape| self.machine.read(tape) == symbols[tape])
+                    }
+                    Point::Watch { tape, cell, symbol } => {
+                        let now = self.machine.cell(*tape, *cell);
+                        let changed = now != symbol;
+                        if changed {
+                            writeln!(out, "cell {} of tape {} changed from {} to {}", cell, *tape + 1, symbol, now)?;
+                            *symbol = now.to_string();
+                        }
+                        changed
+                    }
+                };
+                if hit {
+                    writeln!(out, "stopped at {}", point.describe(*id))?;
+                    stopped = true;
+                }
+            }
+            if stopped || self.machine.halted().is_some() {
+                break;
+            }
+        }
+        writeln!(out, "{}", self.location())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition_reader::TableReader;
+
+    const INCREMENT: &str = "
+        right 0 0 right right
+        right 1 1 right right
+        right _ _ left  carry
+        carry 1 0 left  carry
+        carry 0 1 stay  accept
+        carry _ 1 stay  accept
+    ";
+
+    // Feeds the commands to a debugger on the input and returns everything it printed
+    fn session(input: &str, commands: &[&str]) -> Vec<String> {
+        let table = TableReader::new(INCREMENT).read().unwrap();
+        let mut debugger = Debugger::new(&table, vec![Tape::from_input(input)], None, 1_000);
+        let mut out = Vec::new();
+        for command in commands {
+            debugger.execute(command, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn steps_and_prints_the_tape() {
+        assert_eq!(session("10", &["step 2", "", "print tape"]), [
+            "step 2 in right reading _, next: right _ _ left carry",
+            "step 4 in accept reading 1, halted (Accept)",
+            "tape: 1 [1] (from cell 0)",
+        ]);
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints() {
+        assert_eq!(session("1011", &["break carry 1", "c", "delete 1", "c", "c"]), [
+            "breakpoint 1 in state carry reading 1",
+            "stopped at breakpoint 1 in state carry reading 1",
+            "step 5 in carry reading 1, next: carry 1 0 left carry",
+            "deleted breakpoint 1 in state carry reading 1",
+            "step 8 in accept reading 1, halted (Accept)",
+            "the machine has halted, step 8 in accept reading 1, halted (Accept)",
+        ]);
+    }
+
+    #[test]
+    fn goes_back_and_forth() {
+        assert_eq!(session("1011", &["continue", "back 3", "watch 3", "jump 8", "back 9", "jump 2"]), [
+            "step 8 in accept reading 1, halted (Accept)",
+            "step 5 in carry reading 1, next: carry 1 0 left carry",
+            "watchpoint 1 on cell 3 of tape 1",
+            "step 8 in accept reading 1, halted (Accept)",
+            "there are only 8 steps to go back",
+            "step 2 in right reading 1, next: right 1 1 right right",
+        ]);
+    }
+
+    #[test]
+    fn watchpoints_report_the_change() {
+        assert_eq!(session("011", &["watch 1", "c"]), [
+            "watchpoint 1 on cell 1 of tape 1",
+            "cell 1 of tape 1 changed from 1 to 0",
+            "stopped at watchpoint 1 on cell 1 of tape 1",
+            "step 6 in carry reading 0, next: carry 0 1 stay accept",
+        ]);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::simulator::{Halt, ACCEPT_STATES, REJECT_STATES};
+use crate::transition_types::*;
+use std::collections::HashMap;
+
+// The id of the blank symbol in every table
+pub const BLANK_ID: u32 = 0;
+const NO_TRANSITION: u32 = u32::MAX;
+// Tables that would need more slots than this look their transitions up in a map instead
+const MAX_SLOTS: u64 = 1 << 26;
+
+// Numbers names in the order they are first seen
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    names : Vec<String>,
+    ids : HashMap<String, u32>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: u32) -> &str {
+        &self.names[id as usize]
+    }
+
+    pub fn count(&self) -> usize {
+        self.names.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Slots {
+    Dense(Vec<u32>), //states * symbols^tapes
+    Sparse(HashMap<(u32, Vec<u32>), u32>),
+}
+
+// A transition table with interned states and symbols where a step is a single array lookup.
+// The symbols under the k heads count as one number in base symbols.count(), so (state, symbols) picks a slot
+// holding the transition or NO_TRANSITION
+#[derive(Debug, Clone)]
+pub struct DenseTable {
+    pub states : Interner,
+    pub symbols : Interner,
+    width : u64, //slots per state
+    slots : Slots,
+    halts : Vec<Option<Halt>>, //per state
+    // per transition, the ones of transition i start at i * tapes in write and moves
+    pub next : Vec<u32>,
+    pub write : Vec<u32>,
+    pub moves : Vec<i64>,
+    pub source : Vec<usize>, //the transition's index in the table it was built from
+}
+
+impl DenseTable {
+    // extra holds symbols that aren't in the table but can be on the tape, like those of the input.
+    // Only the first of several transitions for the same state and symbols is kept
+    pub fn new(table: &TransitionTable, tapes: usize, extra: &[&str]) -> DenseTable {
+        DenseTable::with_limit(table, tapes, extra, MAX_SLOTS)
+    }
+
+    // Only tables needing at most max_slots slots are stored densely
+    fn with_limit(table: &TransitionTable, tapes: usize, extra: &[&str], max_slots: u64) -> DenseTable {
+        let mut states = Interner::default();
+        let mut symbols = Interner::default();
+        symbols.intern(BLANK);
+        states.intern(&table.start);
+        for transition in &table.transitions {
+            states.intern(&transition.state);
+            states.intern(&transition.next);
+            for symbol in transition.read.iter().chain(&transition.write) {
+                symbols.intern(symbol);
+            }
+        }
+        for symbol in extra {
+            symbols.intern(symbol);
+        }
+
+        let base = symbols.count() as u64;
+        let width = (0..tapes).try_fold(1u64, |width, _| width.checked_mul(base));
+        let slots = match width.and_then(|width| width.checked_mul(states.count() as u64)) {
+            Some(count) if count <= max_slots => Slots::Dense(vec![NO_TRANSITION; count as usize]),
+            _ => Slots::Sparse(HashMap::new()),
+        };
+        let halts = (0..states.count() as u32).map(|state| {
+            let name = states.name(state);
+            if ACCEPT_STATES.contains(&name) {
+                Some(Halt::Accept)
+            } else if REJECT_STATES.contains(&name) {
+                Some(Halt::Reject)
+            } else {
+                None
+            }
+        }).collect();
+
+        let mut dense = DenseTable {
+            states,
+            symbols,
+            width: width.unwrap_or(u64::MAX),
+            slots,
+            halts,
+            next: Vec::new(),
+            write: Vec::new(),
+            moves: Vec::new(),
+            source: Vec::new(),
+        };
+        // a transition that reads fewer tapes than the machine has can never be taken
+        for (index, transition) in table.transitions.iter().enumerate().filter(|(_, transition)| transition.tapes() == tapes) {
+            let state = dense.state(&transition.state);
+            let read: Vec<u32> = transition.read.iter().map(|symbol| dense.symbol(symbol)).collect();
+            let id = dense.next.len() as u32;
+            let slot = match &mut dense.slots {
+                Slots::Dense(slots) => {
+                    let combined = read.iter().fold(0, |combined, symbol| combined * base + *symbol as u64);
+                    &mut slots[(state as u64 * dense.width + combined) as usize]
+                }
+                Slots::Sparse(slots) => slots.entry((state, read)).or_insert(NO_TRANSITION),
+            };
+            if *slot != NO_TRANSITION {
+                continue;
+            }
+            *slot = id;
+            dense.next.push(dense.state(&transition.next));
+            let write: Vec<u32> = transition.write.iter().map(|symbol| dense.symbol(symbol)).collect();
+            dense.write.extend(write);
+            dense.moves.extend(transition.directions.iter().map(|direction| match direction {
+                Direction::Left => -1,
+                Direction::Right => 1,
+                Direction::Stay => 0,
+            }));
+            dense.source.push(index);
+        }
+        dense
+    }
+
+    fn state(&self, name: &str) -> u32 {
+        self.states.id(name).expect("every state is interned")
+    }
+
+    fn symbol(&self, name: &str) -> u32 {
+        self.symbols.id(name).expect("every symbol is interned")
+    }
+
+    // The transition for a state and the symbols under the heads
+    pub fn lookup(&self, state: u32, read: impl Iterator<Item = u32>) -> Option<u32> {
+        let id = match &self.slots {
+            Slots::Dense(slots) => {
+                let base = self.symbols.count() as u64;
+                let combined = read.fold(0, |combined, symbol| combined * base + symbol as u64);
+                slots[(state as u64 * self.width + combined) as usize]
+            }
+            Slots::Sparse(slots) => slots.get(&(state, read.collect())).copied().unwrap_or(NO_TRANSITION),
+        };
+        (id != NO_TRANSITION).then_some(id)
+    }
+
+    pub fn halts(&self, state: u32) -> Option<Halt> {
+        self.halts[state as usize]
+    }
+}
+
+// A tape of symbol ids that is blank in both directions. The cells between the lowest and highest one ever
+// written sit in a ring buffer, so growing to the left is as cheap as growing to the right
+#[derive(Debug, Clone)]
+pub struct RingTape {
+    cells : Vec<u32>, //a power of two long, or empty
+    start : usize, //where the lowest held cell sits in cells
+    lowest : i64, //the index of the lowest held cell
+    len : usize,
+    head : i64,
+}
+
+impl RingTape {
+    // symbols start at cell lowest
+    pub fn new(lowest: i64, symbols: &[u32], head: i64) -> RingTape {
+        let mut tape = RingTape { cells: Vec::new(), start: 0, lowest, len: 0, head };
+        tape.reserve(symbols.len());
+        tape.cells[..symbols.len()].copy_from_slice(symbols);
+        tape.len = symbols.len();
+        tape
+    }
+
+    pub fn head(&self) -> i64 {
+        self.head
+    }
+
+    pub fn get(&self, index: i64) -> u32 {
+        let offset = index - self.lowest;
+        if offset < 0 || offset as usize >= self.len {
+            BLANK_ID
+        } else {
+            self.cells[(self.start + offset as usize) & (self.cells.len() - 1)]
+        }
+    }
+
+    pub fn read(&self) -> u32 {
+        self.get(self.head)
+    }
+
+    pub fn write(&mut self, symbol: u32) {
+        if self.len == 0 {
+            if symbol == BLANK_ID {
+                return;
+            }
+            self.reserve(1);
+            self.lowest = self.head;
+            self.len = 1;
+        } else if self.head < self.lowest {
+            if symbol == BLANK_ID {
+                return;
+            }
+            let extra = (self.lowest - self.head) as usize;
+            self.reserve(self.len + extra);
+            let mask = self.cells.len() - 1;
+            self.start = (self.start + self.cells.len() - extra) & mask;
+            for offset in 0..extra {
+                self.cells[(self.start + offset) & mask] = BLANK_ID;
+            }
+            self.lowest = self.head;
+            self.len += extra;
+        } else if self.head >= self.lowest + self.len as i64 {
+            if symbol == BLANK_ID {
+                return;
+            }
+            let extra = (self.head - self.lowest) as usize + 1 - self.len;
+            self.reserve(self.len + extra);
+            let mask = self.cells.len() - 1;
+            for offset in self.len..self.len + extra {
+                self.cells[(self.start + offset) & mask] = BLANK_ID;
+            }
+            self.len += extra;
+        }
+        let mask = self.cells.len() - 1;
+        self.cells[(self.start + (self.head - self.lowest) as usize) & mask] = symbol;
+    }
+
+    pub fn shift(&mut self, offset: i64) {
+        self.head += offset;
+    }
+
+    // Doubles the buffer until it holds size cells, laying the held ones out from the start again
+    fn reserve(&mut self, size: usize) {
+        if size <= self.cells.len() {
+            return;
+        }
+        let capacity = size.next_power_of_two().max(16);
+        let mut cells = vec![BLANK_ID; capacity];
+        for (offset, cell) in cells.iter_mut().enumerate().take(self.len) {
+            *cell = self.cells[(self.start + offset) & (self.cells.len() - 1)];
+        }
+        self.cells = cells;
+        self.start = 0;
+    }
+
+    // The index of the lowest held cell and the held cells from there on, blanks at either end included
+    pub fn held(&self) -> (i64, impl Iterator<Item = u32> + '_) {
+        (self.lowest, (0..self.len).map(|offset| self.cells[(self.start + offset) & (self.cells.len() - 1)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition_reader::TableReader;
+
+    #[test]
+    fn ring_tapes_grow_both_ways() {
+        let mut tape = RingTape::new(0, &[1, 2], 0);
+        for _ in 0..40 {
+            tape.shift(-1);
+            tape.write(3);
+        }
+        tape.shift(45);
+        tape.write(4);
+        assert_eq!(tape.get(-40), 3);
+        assert_eq!(tape.get(0), 1);
+        assert_eq!(tape.get(1), 2);
+        assert_eq!(tape.get(4), BLANK_ID);
+        assert_eq!(tape.get(5), 4);
+        assert_eq!(tape.get(6), BLANK_ID);
+        let (lowest, cells) = tape.held();
+        assert_eq!((lowest, cells.count()), (-40, 46));
+    }
+
+    #[test]
+    fn dense_and_sparse_tables_agree() {
+        let one = "
+            q a b right q
+            q a c right r
+            q _ _ stay  accept
+            r b a left  q
+            r c _ left  reject
+        ";
+        let two = "
+            q a,_ a,x right,stay q
+            q a,_ b,b left,left  r
+            q _,x _,_ stay,stay  accept
+            r x,a a,a right,left q
+            r _,_ x,_ stay,right r
+        ";
+        for (text, tapes) in [(one, 1), (two, 2)] {
+            let table = TableReader::new(text).read().unwrap();
+            let dense = DenseTable::new(&table, tapes, &["d"]);
+            let sparse = DenseTable::with_limit(&table, tapes, &["d"], 0);
+            assert!(matches!(dense.slots, Slots::Dense(_)));
+            assert!(matches!(sparse.slots, Slots::Sparse(_)));
+            assert_eq!((&dense.next, &dense.write, &dense.moves, &dense.source), (&sparse.next, &sparse.write, &sparse.moves, &sparse.source));
+
+            // every state against every combination of symbols under the heads, most of them misses
+            let base = dense.symbols.count() as u32;
+            let mut hits = 0;
+            for state in 0..dense.states.count() as u32 {
+                assert_eq!(dense.states.name(state), sparse.states.name(state));
+                assert_eq!(dense.halts(state), sparse.halts(state));
+                for combined in 0..base.pow(tapes as u32) {
+                    let read: Vec<u32> = (0..tapes as u32).rev().map(|tape| combined / base.pow(tape) % base).collect();
+                    let found = dense.lookup(state, read.iter().copied());
+                    assert_eq!(found, sparse.lookup(state, read.iter().copied()), "{} reading {:?}", dense.states.name(state), read);
+                    hits += found.is_some() as usize;
+                }
+            }
+            // the second transition for q reading a is dropped
+            assert_eq!(hits, 4);
+        }
+    }
+
+    #[test]
+    fn keeps_the_first_transition_and_knows_halting_states() {
+        let table = TransitionTable {
+            start: "q".to_string(),
+            transitions: vec![
+                Transition::new("q", "a", "b", Direction::Right, "q"),
+                Transition::new("q", "a", "c", Direction::Right, "r"),
+                Transition::new("q", "_", "_", Direction::Stay, "accept"),
+            ],
+        };
+        let dense = DenseTable::new(&table, 1, &[]);
+        let a = dense.symbols.id("a").unwrap();
+        assert!(matches!(dense.slots, Slots::Dense(_)));
+        assert_eq!(dense.lookup(0, [a].into_iter()), Some(0));
+        assert_eq!(dense.lookup(0, [BLANK_ID].into_iter()), Some(1));
+        assert_eq!(dense.source, [0, 2]);
+        assert_eq!(dense.halts(dense.states.id("accept").unwrap()), Some(Halt::Accept));
+
+        // 100 tapes need more slots than there are atoms in the universe
+        let wide = DenseTable::new(&table, 100, &[]);
+        assert!(matches!(wide.slots, Slots::Sparse(_)));
+        assert_eq!(wide.lookup(0, [a].into_iter()), None);
+    }
+}
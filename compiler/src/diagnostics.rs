@@ -0,0 +1,340 @@
+
+use crate::lexer_types::{Position, Span};
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+const TAB_WIDTH: usize = 4;
+
+// The text of one input file with the byte offset of every line start, so spans can be shown without rereading it
+pub struct SourceFile {
+    pub name : String,
+    pub content : String,
+    line_starts : Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, content: &str) -> SourceFile {
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        SourceFile { name: name.to_string(), content: content.to_string(), line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // Zero based line containing the byte offset
+    pub fn row(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(row) => row,
+            Err(row) => row - 1,
+        }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let row = self.row(offset);
+        let col = self.content[self.line_starts[row]..offset.min(self.content.len())].chars().count();
+        Position { row, col }
+    }
+
+    // Byte offset of a row and char column
+    pub fn offset(&self, position: Position) -> usize {
+        let line = self.line(position.row);
+        self.line_start(position.row) + line.char_indices().nth(position.col).map(|(offset, _)| offset).unwrap_or(line.len())
+    }
+
+    // The line without its line ending
+    pub fn line(&self, row: usize) -> &str {
+        let start = self.line_starts[row];
+        let end = self.line_starts.get(row + 1).map(|&next| next - 1).unwrap_or(self.content.len());
+        self.content[start..end].trim_end_matches('\r')
+    }
+
+    fn line_start(&self, row: usize) -> usize {
+        self.line_starts[row]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span : Span,
+    pub message : String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity : Severity,
+    pub message : String,
+    pub primary : Label,
+    pub secondary : Vec<Label>,
+    pub notes : Vec<String>,
+    pub help : Option<String>,
+}
+
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: &str, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.to_string(),
+            primary: Label { span, message: String::new() },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn error(message: &str, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: &str, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message, span)
+    }
+
+    pub fn with_primary_label(mut self, message: &str) -> Diagnostic {
+        self.primary.message = message.to_string();
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: &str) -> Diagnostic {
+        self.secondary.push(Label { span, message: message.to_string() });
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    // Renders in the style of rustc:
+    //
+    // error: expected ';' but found '5'
+    //  --> input.txt:9:13
+    //   |
+    // 9 |   let e = 5 5;
+    //   |             ^ expected ';'
+    //   |
+    //   = note: ...
+    pub fn render(&self, source: &SourceFile, colour: bool) -> String {
+        let paint = |code: &'static str| if colour { code } else { "" };
+        let reset = paint(RESET);
+        let blue = paint(BLUE);
+
+        // every label as (span, message, is_primary), clamped to the file
+        let mut labels: Vec<(Span, &str, bool)> = vec![(self.primary.span, self.primary.message.as_str(), true)];
+        labels.extend(self.secondary.iter().map(|label| (label.span, label.message.as_str(), false)));
+        for (span, _, _) in labels.iter_mut() {
+            span.start = span.start.min(source.content.len());
+            span.end = span.end.clamp(span.start, source.content.len());
+        }
+
+        let mut rows: Vec<usize> = Vec::new();
+        for (span, _, _) in &labels {
+            let (first, last) = self.rows(source, *span);
+            // long multi-line spans only show their first two and last two lines
+            rows.extend((first..=last).filter(|&row| row <= first + 1 || row + 1 >= last));
+        }
+        rows.sort_unstable();
+        rows.dedup();
+
+        let gutter = rows.last().map(|row| (row + 1).to_string().len()).unwrap_or(1);
+        let pad = " ".repeat(gutter);
+        let mut out = String::new();
+
+        let primary = source.position(self.primary.span.start);
+        let _ = writeln!(out, "{}{}{}: {}{}{}", paint(self.severity.colour()), self.severity.name(), reset, paint(BOLD), self.message, reset);
+        let _ = writeln!(out, "{}{}-->{} {}:{}:{}", pad, blue, reset, source.name, primary.row + 1, primary.col + 1);
+        let _ = writeln!(out, "{} {}|{}", pad, blue, reset);
+
+        let mut previous: Option<usize> = None;
+        for &row in &rows {
+            if previous.map(|previous| row > previous + 1).unwrap_or(false) {
+                let _ = writeln!(out, "{}{}...{}", " ".repeat(gutter.saturating_sub(2)), blue, reset);
+            }
+            previous = Some(row);
+
+            let line = source.line(row);
+            let _ = writeln!(out, "{}{:>width$} |{} {}", blue, row + 1, reset, expand_tabs(line), width = gutter);
+
+            for (span, message, is_primary) in &labels {
+                let (first, last) = self.rows(source, *span);
+                if row < first || row > last {
+                    continue;
+                }
+                let line_start = source.line_start(row);
+                let start = if row == first { span.start - line_start } else { 0 };
+                let end = if row == last { span.end - line_start } else { line.len() };
+                let start = start.min(line.len());
+                let end = end.clamp(start, line.len());
+
+                let offset = display_width(&line[..start]);
+                let width = display_width(&line[start..end]).max(1);
+                let (marker, marker_colour) = if *is_primary { ('^', paint(self.severity.colour())) } else { ('-', blue) };
+                let label = if row == last && !message.is_empty() { format!(" {}", message) } else { String::new() };
+                let _ = writeln!(out, "{} {}|{} {}{}{}{}{}", pad, blue, reset, " ".repeat(offset), marker_colour, marker.to_string().repeat(width), label, reset);
+            }
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            let _ = writeln!(out, "{} {}|{}", pad, blue, reset);
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "{} {}={} {}note{}: {}", pad, blue, reset, paint(BOLD), reset, note);
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{} {}={} {}help{}: {}", pad, blue, reset, paint(BOLD), reset, help);
+        }
+        out
+    }
+
+    // First and last row touched by a span, an empty span still touches the row it sits on
+    fn rows(&self, source: &SourceFile, span: Span) -> (usize, usize) {
+        let first = source.row(span.start);
+        let last = if span.end > span.start { source.row(span.end - 1) } else { first };
+        (first, last.min(source.line_count() - 1))
+    }
+}
+
+// Colour is used when stderr is a terminal and NO_COLOR isn't set
+pub fn emit(diagnostics: &[Diagnostic], source: &SourceFile) {
+    let colour = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(source, colour));
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { TAB_WIDTH } else { 1 }).sum()
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The span of the first occurrence of text in the source
+    fn find(source: &SourceFile, text: &str) -> Span {
+        let start = source.content.find(text).unwrap();
+        Span { start, end: start + text.len() }
+    }
+
+    #[test]
+    fn points_at_a_single_line() {
+        let source = SourceFile::new("input.txt", "let a = 1;\nlet e = 5 5;\n");
+        let span = Span { start: 21, end: 22 };
+        let rendered = Diagnostic::error("expected ';' but found '5'", span)
+            .with_primary_label("expected ';'")
+            .with_note("statements end in ';'")
+            .with_help("add a ';' after the first 5")
+            .render(&source, false);
+        assert_eq!(rendered, "\
+error: expected ';' but found '5'
+ --> input.txt:2:11
+  |
+2 | let e = 5 5;
+  |           ^ expected ';'
+  |
+  = note: statements end in ';'
+  = help: add a ';' after the first 5
+");
+    }
+
+    #[test]
+    fn secondary_labels_and_tabs_line_up() {
+        let source = SourceFile::new("call.txt", "let f = g(1,\n\t2;\n");
+        let rendered = Diagnostic::error("expected ')' but found ';'", find(&source, ";"))
+            .with_primary_label("expected ')'")
+            .with_label(find(&source, "("), "unclosed delimiter opened here")
+            .render(&source, false);
+        // the tab is shown as four spaces and the caret still sits under the ';'
+        assert_eq!(rendered, "\
+error: expected ')' but found ';'
+ --> call.txt:2:3
+  |
+1 | let f = g(1,
+  |          - unclosed delimiter opened here
+2 |     2;
+  |      ^ expected ')'
+");
+    }
+
+    #[test]
+    fn long_spans_elide_their_middle() {
+        let source = SourceFile::new("loop.txt", "while x {\n  a;\n  b;\n  c;\n  d;\n}\n");
+        let rendered = Diagnostic::warning("loop never ends", find(&source, "while x {\n  a;\n  b;\n  c;\n  d;\n}"))
+            .with_primary_label("this loop")
+            .render(&source, false);
+        assert_eq!(rendered, "\
+warning: loop never ends
+ --> loop.txt:1:1
+  |
+1 | while x {
+  | ^^^^^^^^^
+2 |   a;
+  | ^^^^
+...
+5 |   d;
+  | ^^^^
+6 | }
+  | ^ this loop
+");
+    }
+
+    #[test]
+    fn colour_is_optional() {
+        let source = SourceFile::new("loop.txt", "while x {\n  a;\n}\n");
+        let diagnostic = Diagnostic::error("unknown variable", find(&source, "a")).with_label(find(&source, "x"), "declared here");
+        let plain = diagnostic.render(&source, false);
+        assert!(!plain.contains('\x1b'));
+        let coloured = diagnostic.render(&source, true);
+        assert!(coloured.starts_with("\x1b[1;31merror\x1b[0m: \x1b[1munknown variable\x1b[0m\n"));
+        assert!(coloured.contains("\x1b[1;34m- declared here\x1b[0m"));
+        // without the escapes both say the same
+        let mut stripped = String::new();
+        let mut escape = false;
+        for c in coloured.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                _ if escape => {}
+                _ => stripped.push(c),
+            }
+        }
+        assert_eq!(stripped, plain);
+    }
+}
@@ -0,0 +1,489 @@
+
+use crate::checker::Checker;
+use crate::cli::*;
+use crate::codegen;
+use crate::debugger::Debugger;
+use crate::diagnostics::{self, Severity, SourceFile};
+use crate::explorer::{self, ExplorationReport, Outcome};
+use crate::formats::{self, Format};
+use crate::formatter;
+use crate::ir_types::{IrProgram, Layout};
+use crate::lexer::Lexer;
+use crate::lexer_types::Token;
+use crate::lowering::Lowering;
+use crate::macro_machine::MacroMachine;
+use crate::multitape;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::parser_types::Program;
+use crate::simulator::{Halt, Machine, RunReport, Tape};
+use crate::trace::Tracer;
+use crate::transition_reader::TableReader;
+use crate::transition_types::TransitionTable;
+
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum Failure {
+    Usage(String),
+    Io(String),
+    Input, //the diagnostics have already been printed
+    Rejected,
+    StepLimit,
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Io(_) => EXIT_IO,
+            Failure::Input => EXIT_INPUT_ERROR,
+            Failure::Rejected => EXIT_REJECTED,
+            Failure::StepLimit => EXIT_STEP_LIMIT,
+        }
+    }
+
+    pub fn report(&self) {
+        match self {
+            Failure::Usage(message) => eprintln!("error: {}\n\n{}", message, USAGE),
+            Failure::Io(message) => eprintln!("error: {}", message),
+            Failure::Input | Failure::Rejected | Failure::StepLimit => {}
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("compiler data always serializes") + "\n"
+}
+
+fn write_file(path: &str, contents: &str) -> Result<(), Failure> {
+    fs::write(path, contents).map_err(|error| Failure::Io(format!("couldn't write {}: {}", path, error)))
+}
+
+// Writes to -o when given, stdout otherwise
+fn write_output(options: &Options, contents: &str) -> Result<(), Failure> {
+    match &options.output {
+        Some(path) => write_file(path, contents),
+        None => {
+            // a closed pipe (e.g. `| head`) isn't worth failing over
+            match io::stdout().write_all(contents.as_bytes()) {
+                Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(Failure::Io(format!("couldn't write to stdout: {}", error))),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+// Writes <dir>/stepN.json when --steps is given
+fn write_step<T: Serialize>(options: &Options, step: usize, value: &T) -> Result<(), Failure> {
+    match &options.steps {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|error| Failure::Io(format!("couldn't create {}: {}", dir, error)))?;
+            let path = Path::new(dir).join(format!("step{}.json", step));
+            write_file(&path.to_string_lossy(), &to_json(value))
+        }
+        None => Ok(()),
+    }
+}
+
+fn lex(options: &Options, source: &SourceFile) -> Result<Vec<Token>, Failure> {
+    match Lexer::new(&source.content).tokenize() {
+        Ok(tokens) => {
+            write_step(options, 1, &tokens)?;
+            Ok(tokens)
+        }
+        Err(errors) => {
+            diagnostics::emit(&errors.iter().map(|error| error.diagnostic()).collect::<Vec<_>>(), source);
+            Err(Failure::Input)
+        }
+    }
+}
+
+fn parse(options: &Options, source: &SourceFile, tokens: Vec<Token>) -> Result<Program, Failure> {
+    let (program, errors) = Parser::new(tokens).parse();
+    // the partial tree is still written so the errors can be inspected against it
+    write_step(options, 2, &program)?;
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        diagnostics::emit(&errors.iter().map(|error| error.diagnostic()).collect::<Vec<_>>(), source);
+        Err(Failure::Input)
+    }
+}
+
+fn read_table(options: &Options, source: &SourceFile) -> Result<TransitionTable, Failure> {
+    match table_format(options, source) {
+        Some(Format::Tm) | None => TableReader::new(&source.content).read().map_err(|errors| {
+            diagnostics::emit(&errors.iter().map(|error| error.diagnostic()).collect::<Vec<_>>(), source);
+            Failure::Input
+        }),
+        Some(format) => {
+            let input: Vec<String> = options.tapes.iter().flat_map(|tape| tape.chars().map(String::from)).collect();
+            formats::import(format, source, &input).map_err(|errors| {
+                diagnostics::emit(&errors.iter().map(|error| error.diagnostic()).collect::<Vec<_>>(), source);
+                Failure::Input
+            })
+        }
+    }
+}
+
+fn lower(options: &Options, source: &SourceFile) -> Result<IrProgram, Failure> {
+    let tokens = lex(options, source)?;
+    let program = parse(options, source, tokens)?;
+    match Lowering::new(&options.encodings).lower(&program) {
+        Ok(ir) => {
+            write_step(options, 3, &ir)?;
+            Ok(ir)
+        }
+        Err(errors) => {
+            diagnostics::emit(&errors.iter().map(|error| error.diagnostic()).collect::<Vec<_>>(), source);
+            Err(Failure::Input)
+        }
+    }
+}
+
+// Everything a program goes through before it becomes a transition table
+fn compile(options: &Options, source: &SourceFile) -> Result<(TransitionTable, Layout), Failure> {
+    let ir = lower(options, source)?;
+    let table = codegen::generate(&ir);
+    Ok((table, ir.layout))
+}
+
+// Under --strict a nondeterministic table is an error
+fn read_strict_table(options: &Options, source: &SourceFile) -> Result<TransitionTable, Failure> {
+    let table = read_table(options, source)?;
+    if options.strict {
+        let choices = explorer::choices(&table);
+        if !choices.is_empty() {
+            diagnostics::emit(&choices.iter().map(|choice| choice.diagnostic(source, Severity::Error)).collect::<Vec<_>>(), source);
+            return Err(Failure::Input);
+        }
+    }
+    Ok(table)
+}
+
+// A table read from source, reduced to a single tape when asked for
+fn load_table(options: &Options, source: &SourceFile) -> Result<TransitionTable, Failure> {
+    let table = read_strict_table(options, source)?;
+    if options.single_tape && table.tapes() > 1 {
+        Ok(multitape::to_single_tape(&table))
+    } else {
+        Ok(table)
+    }
+}
+
+// The sizes go to stderr so they don't end up in the table build writes to stdout
+fn optimize(options: &Options, table: TransitionTable) -> TransitionTable {
+    if !options.optimize {
+        return table;
+    }
+    let (optimized, report) = optimizer::optimize(&table);
+    let size = |counts: optimizer::Counts| format!("{} states, {} transitions", counts.states, counts.transitions);
+    eprintln!("optimized {} into {}", size(report.before), size(report.after()));
+    for (pass, counts) in &report.passes {
+        eprintln!("    {:<20}{}", pass, size(*counts));
+    }
+    optimized
+}
+
+fn build_table(options: &Options, source: &SourceFile) -> Result<TransitionTable, Failure> {
+    let table = if is_table(options, source) {
+        load_table(options, source)?
+    } else {
+        compile(options, source)?.0
+    };
+    Ok(optimize(options, table))
+}
+
+// The format an input table is written in, None for a program
+fn table_format(options: &Options, source: &SourceFile) -> Option<Format> {
+    if options.from.is_some() {
+        options.from
+    } else if source.name.ends_with(".tm") {
+        Some(Format::Tm)
+    } else if source.name.ends_with(".jff") {
+        Some(Format::Jflap)
+    } else {
+        None
+    }
+}
+
+fn is_table(options: &Options, source: &SourceFile) -> bool {
+    table_format(options, source).is_some()
+}
+
+fn build(options: &Options, source: &SourceFile) -> Result<(), Failure> {
+    match options.emit {
+        Emit::Tm => {
+            let table = build_table(options, source)?;
+            let name = Path::new(&source.name).file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+            let exported = formats::export(options.target, &table, &name).map_err(Failure::Usage)?;
+            write_output(options, &exported)
+        }
+        _ if is_table(options, source) => Err(Failure::Usage("a transition table can only be emitted as tm".to_string())),
+        Emit::Tokens => {
+            let tokens = lex(options, source)?;
+            write_output(options, &to_json(&tokens))
+        }
+        Emit::Ast => {
+            let tokens = lex(options, source)?;
+            let program = parse(options, source, tokens)?;
+            write_output(options, &to_json(&program))
+        }
+        Emit::Ir => {
+            let ir = lower(options, source)?;
+            write_output(options, &ir.to_string())
+        }
+    }
+}
+
+// The table to simulate, the layout of a compiled program and the tapes it starts on.
+// A compiled program sets up its own tape, so --tape only applies to tables
+fn prepare_machine(options: &Options, source: &SourceFile) -> Result<(TransitionTable, Option<Layout>, Vec<Tape>), Failure> {
+    if is_table(options, source) {
+        let table = optimize(options, load_table(options, source)?);
+        if options.tapes.len() > table.tapes() {
+            return Err(Failure::Usage(format!("{} has {} tapes but --tape was given {} times", source.name, table.tapes(), options.tapes.len())));
+        }
+        let tapes = options.tapes.iter().map(|input| Tape::from_input(input)).collect();
+        Ok((table, None, tapes))
+    } else {
+        let (table, layout) = compile(options, source)?;
+        Ok((optimize(options, table), Some(layout), Vec::new()))
+    }
+}
+
+fn run_machine(options: &Options, source: &SourceFile) -> Result<(), Failure> {
+    let (table, layout, tapes) = prepare_machine(options, source)?;
+    let nondeterministic = !explorer::choices(&table).is_empty();
+    if options.accelerate && table.tapes() > 1 {
+        return Err(Failure::Usage(format!("--accelerate only runs single tape tables, {} has {} tapes, add --single-tape", source.name, table.tapes())));
+    }
+    if options.accelerate && nondeterministic {
+        return Err(Failure::Usage(format!("--accelerate only runs deterministic tables and {} has several transitions for the same state and symbol", source.name)));
+    }
+    // only opened once the options are known to be fine, so a rejected run leaves an old trace alone
+    let mut tracer = open_trace(options)?;
+    if nondeterministic {
+        let started = Instant::now();
+        let report = explorer::explore(&table, tapes.clone(), options.max_steps, options.max_configurations);
+        if options.benchmark {
+            let elapsed = started.elapsed().as_secs_f64();
+            println!("{}: {:?} after {} configurations in {:.3}s, {:.0} configurations/s",
+                source.name, report.outcome, report.configurations, elapsed, report.configurations as f64 / elapsed);
+            return exploration_result(&report);
+        }
+        // only the path that was found is traced, not every branch
+        if let Some(tracer) = &mut tracer {
+            tracer.record_path(tapes, &report.path, &report.state);
+        }
+        finish_trace(options, tracer)?;
+        return report_exploration(options, &report);
+    }
+    let started = Instant::now();
+    if options.accelerate {
+        let tape = tapes.into_iter().next().unwrap_or_default();
+        let mut machine = MacroMachine::new(&table, &tape);
+        let built = started.elapsed().as_secs_f64();
+        let started = Instant::now();
+        let report = machine.run(options.max_steps);
+        let timing = Timing { built, ran: started.elapsed().as_secs_f64(), macro_steps: Some(machine.macro_steps()) };
+        return finish_run(options, source, &report, layout.as_ref(), timing);
+    }
+    let mut machine = Machine::new(&table, tapes);
+    let built = started.elapsed().as_secs_f64();
+    let started = Instant::now();
+    let report = match &mut tracer {
+        Some(tracer) => machine.run_observed(options.max_steps, |machine| {
+            if tracer.wants(machine.steps(), machine.state()) {
+                tracer.record(machine.steps(), machine.state(), &machine.tapes(), machine.next_transition());
+            }
+        }),
+        None => machine.run(options.max_steps),
+    };
+    let elapsed = started.elapsed().as_secs_f64();
+    finish_trace(options, tracer)?;
+    finish_run(options, source, &report, layout.as_ref(), Timing { built, ran: elapsed, macro_steps: None })
+}
+
+// What --benchmark reports about a run, in seconds
+struct Timing {
+    built : f64,
+    ran : f64,
+    macro_steps : Option<usize>, //only for --accelerate
+}
+
+fn finish_run(options: &Options, source: &SourceFile, report: &RunReport, layout: Option<&Layout>, timing: Timing) -> Result<(), Failure> {
+    if options.benchmark {
+        let outcome = match report.halt {
+            Some(halt) => format!("halted ({:?})", halt),
+            None => "still running".to_string(),
+        };
+        let macro_steps = timing.macro_steps.map(|count| format!(" ({} macro steps)", count)).unwrap_or_default();
+        println!("{}: {} after {} steps{} in {:.3}s, {:.0} steps/s, table built in {:.3}s",
+            source.name, outcome, report.steps, macro_steps, timing.ran, report.steps as f64 / timing.ran, timing.built);
+    } else {
+        print_report(report, layout);
+    }
+    if let Some(path) = &options.output {
+        write_file(path, &to_json(report))?;
+    }
+
+    match report.halt {
+        Some(Halt::Reject) => Err(Failure::Rejected),
+        Some(_) => Ok(()),
+        None => Err(Failure::StepLimit),
+    }
+}
+
+fn print_report(report: &RunReport, layout: Option<&Layout>) {
+    match report.halt {
+        Some(halt) => println!("halted ({:?}) in state {} after {} steps", halt, report.state, report.steps),
+        None => println!("still running in state {} after {} steps", report.state, report.steps),
+    }
+    print_tapes(&report.tapes);
+    if let Some(layout) = layout {
+        for (name, value) in codegen::decode(layout, &report.tapes[0]) {
+            println!("{} = {}", name, value);
+        }
+    }
+}
+
+type TraceFile = Tracer<io::BufWriter<fs::File>>;
+
+fn open_trace(options: &Options) -> Result<Option<TraceFile>, Failure> {
+    match &options.trace {
+        Some(path) => {
+            let file = fs::File::create(path).map_err(|error| Failure::Io(format!("couldn't write {}: {}", path, error)))?;
+            Ok(Some(Tracer::new(&options.trace_options, io::BufWriter::new(file))))
+        }
+        None => Ok(None),
+    }
+}
+
+fn finish_trace(options: &Options, tracer: Option<TraceFile>) -> Result<(), Failure> {
+    match (tracer, &options.trace) {
+        (Some(tracer), Some(path)) => tracer.finish().map_err(|error| Failure::Io(format!("couldn't write {}: {}", path, error))),
+        _ => Ok(()),
+    }
+}
+
+// Commands come from stdin, so a session can also be scripted
+fn debug(options: &Options, source: &SourceFile) -> Result<(), Failure> {
+    let (table, layout, tapes) = prepare_machine(options, source)?;
+    if !explorer::choices(&table).is_empty() {
+        eprintln!("note: {} is nondeterministic, debug follows the first transition of every choice", source.name);
+    }
+    let mut debugger = Debugger::new(&table, tapes, layout, options.max_steps);
+    debugger.repl(io::stdin().lock(), io::stdout()).map_err(|error| Failure::Io(format!("debugger: {}", error)))
+}
+
+fn print_tapes(tapes: &[Tape]) {
+    if let [tape] = tapes {
+        println!("tape: {}", tape);
+    } else {
+        for (index, tape) in tapes.iter().enumerate() {
+            println!("tape {}: {}", index + 1, tape);
+        }
+    }
+}
+
+fn report_exploration(options: &Options, report: &ExplorationReport) -> Result<(), Failure> {
+    match report.outcome {
+        Outcome::Accepted => {
+            println!("accepted after {} steps, {} configurations explored", report.path.len(), report.configurations);
+            println!("path:");
+            for (step, transition) in report.path.iter().enumerate() {
+                println!("{:>6}  {}", step + 1, transition);
+            }
+        }
+        Outcome::Rejected => println!("rejected, every branch halted without accepting after {} configurations", report.configurations),
+        Outcome::StepLimit => println!("no branch accepted within {} steps, {} configurations explored", options.max_steps, report.configurations),
+        Outcome::OutOfConfigurations => println!("no branch accepted within {} configurations", report.configurations),
+    }
+    print_tapes(&report.tapes);
+    if let Some(path) = &options.output {
+        write_file(path, &to_json(report))?;
+    }
+    exploration_result(report)
+}
+
+fn exploration_result(report: &ExplorationReport) -> Result<(), Failure> {
+    match report.outcome {
+        Outcome::Accepted => Ok(()),
+        Outcome::Rejected => Err(Failure::Rejected),
+        Outcome::StepLimit | Outcome::OutOfConfigurations => Err(Failure::StepLimit),
+    }
+}
+
+fn format(options: &Options, source: &SourceFile) -> Result<(), Failure> {
+    let formatted = if is_table(options, source) {
+        if table_format(options, source) != Some(Format::Tm) {
+            return Err(Failure::Usage("fmt only rewrites tables in the README format".to_string()));
+        }
+        // only well formed tables are rewritten
+        read_table(options, source)?;
+        formatter::format_table(&source.content)
+    } else {
+        let tokens = lex(options, source)?;
+        formatter::format_source(&source.content, &tokens)
+    };
+    write_output(options, &formatted)
+}
+
+fn run_input(options: &Options, path: &str) -> Result<(), Failure> {
+    let content = fs::read_to_string(path).map_err(|error| Failure::Io(format!("couldn't read {}: {}", path, error)))?;
+    let source = SourceFile::new(path, &content);
+
+    match options.command {
+        Command::Lex if is_table(options, &source) => Err(Failure::Usage("lex only works on programs, not on transition tables".to_string())),
+        Command::Lex => {
+            let tokens = lex(options, &source)?;
+            write_output(options, &to_json(&tokens))
+        }
+        Command::Parse if is_table(options, &source) => {
+            let table = read_table(options, &source)?;
+            write_output(options, &to_json(&table))
+        }
+        Command::Parse => {
+            let tokens = lex(options, &source)?;
+            let program = parse(options, &source, tokens)?;
+            write_output(options, &to_json(&program))
+        }
+        Command::Check if is_table(options, &source) => {
+            // the checker's findings are warnings, the table itself is fine
+            let table = read_strict_table(options, &source)?;
+            diagnostics::emit(&Checker::new(&table, &source).check(), &source);
+            Ok(())
+        }
+        Command::Check => lower(options, &source).map(|_| ()),
+        Command::Build => build(options, &source),
+        Command::Run => run_machine(options, &source),
+        Command::Debug => debug(options, &source),
+        Command::Fmt => format(options, &source),
+        Command::Help => Ok(()),
+    }
+}
+
+// Every input is processed even after one fails, so check reports all files at once
+pub fn run(options: &Options) -> Result<(), Failure> {
+    if options.command == Command::Help {
+        let _ = writeln!(io::stdout(), "{}", USAGE);
+        return Ok(());
+    }
+
+    let mut result = Ok(());
+    for input in &options.inputs {
+        if let Err(failure) = run_input(options, input) {
+            if let Failure::Usage(_) | Failure::Io(_) = failure {
+                return Err(failure);
+            }
+            result = Err(failure);
+        }
+    }
+    result
+}
@@ -0,0 +1,206 @@
+use crate::diagnostics::{Diagnostic, Severity, SourceFile};
+use crate::simulator::{self, Tape, ACCEPT_STATES, REJECT_STATES};
+use crate::transition_reader::span;
+use crate::transition_types::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Transitions sharing a state and the symbols they read, which makes the table nondeterministic
+#[derive(Debug, Clone)]
+pub struct Choice<'a> {
+    pub transitions : Vec<&'a Transition>,
+}
+
+impl Choice<'_> {
+    // An error under --strict, otherwise check warns about it
+    pub fn diagnostic(&self, source: &SourceFile, severity: Severity) -> Diagnostic {
+        let first = self.transitions[0];
+        let message = format!("state '{}' has {} transitions for '{}'", first.state, self.transitions.len(), first.read.join(","));
+        let mut diagnostic = Diagnostic::new(severity, &message, span(source, self.transitions[1]))
+            .with_primary_label("another choice")
+            .with_label(span(source, first), "first choice");
+        for transition in &self.transitions[2..] {
+            diagnostic = diagnostic.with_label(span(source, transition), "another choice");
+        }
+        match severity {
+            Severity::Error => diagnostic
+                .with_note("a deterministic machine has at most one transition per state and read symbol")
+                .with_help("run without --strict to explore every choice"),
+            Severity::Warning => diagnostic
+                .with_note("the machine is nondeterministic, run explores every choice")
+                .with_help("use --strict to treat this as an error"),
+        }
+    }
+}
+
+// Every (state, symbols) pair with more than one transition, in table order
+pub fn choices(table: &TransitionTable) -> Vec<Choice<'_>> {
+    let mut groups: HashMap<(&str, &[String]), usize> = HashMap::new();
+    let mut choices: Vec<Choice> = Vec::new();
+    for transition in &table.transitions {
+        let key = (transition.state.as_str(), transition.read.as_slice());
+        match groups.get(&key) {
+            Some(&index) => choices[index].transitions.push(transition),
+            None => {
+                groups.insert(key, choices.len());
+                choices.push(Choice { transitions: vec![transition] });
+            }
+        }
+    }
+    choices.retain(|choice| choice.transitions.len() > 1);
+    choices
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Outcome {
+    Accepted,
+    Rejected, //every branch halted without accepting
+    StepLimit, //no branch accepted within max_steps
+    OutOfConfigurations,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplorationReport {
+    pub outcome : Outcome,
+    pub configurations : usize,
+    // the accepting configuration and how it was reached, otherwise the last one explored
+    pub state : String,
+    pub path : Vec<Transition>,
+    pub tapes : Vec<Tape>,
+}
+
+struct Configuration {
+    state : String,
+    tapes : Vec<Tape>,
+    parent : Option<usize>,
+    transition : Option<usize>, //index into the table
+    steps : usize,
+}
+
+// What makes two configurations the same, independent of how much blank tape each has allocated
+type Key = (String, Vec<(Vec<String>, i64, i64)>);
+
+fn key(state: &str, tapes: &[Tape]) -> Key {
+    let tapes = tapes.iter()
+        .map(|tape| {
+            let offset = tape.bounds().map(|(lowest, _)| lowest).unwrap_or(0);
+            (tape.symbols().iter().map(|symbol| symbol.to_string()).collect(), offset, tape.head())
+        })
+        .collect();
+    (state.to_string(), tapes)
+}
+
+// Follows every choice breadth first, so the first accepting configuration found has the shortest path.
+// Configurations seen before aren't explored again, at most max_configurations are kept
+pub fn explore(table: &TransitionTable, mut tapes: Vec<Tape>, max_steps: usize, max_configurations: usize) -> ExplorationReport {
+    let mut transitions: HashMap<(&str, &[String]), Vec<usize>> = HashMap::new();
+    for (index, transition) in table.transitions.iter().enumerate() {
+        transitions.entry((&transition.state, &transition.read)).or_default().push(index);
+    }
+    if tapes.len() < table.tapes() {
+        tapes.resize(table.tapes(), Tape::new());
+    }
+
+    let mut seen = HashSet::from([key(&table.start, &tapes)]);
+    let mut configurations = vec![Configuration { state: table.start.clone(), tapes, parent: None, transition: None, steps: 0 }];
+    let mut queue = VecDeque::from([0]);
+    let mut last = 0;
+    let mut cut_off = false;
+
+    let outcome = loop {
+        let Some(index) = queue.pop_front() else {
+            break if cut_off { Outcome::StepLimit } else { Outcome::Rejected };
+        };
+        last = index;
+        let current = &configurations[index];
+        if ACCEPT_STATES.contains(&current.state.as_str()) {
+            break Outcome::Accepted;
+        }
+        if REJECT_STATES.contains(&current.state.as_str()) {
+            continue;
+        }
+        if current.steps == max_steps {
+            cut_off = true;
+            continue;
+        }
+
+        let read: Vec<String> = current.tapes.iter().map(|tape| tape.read().to_string()).collect();
+        let mut children = Vec::new();
+        for &choice in transitions.get(&(current.state.as_str(), read.as_slice())).into_iter().flatten() {
+            let transition = &table.transitions[choice];
+            let mut tapes = current.tapes.clone();
+            simulator::apply(&mut tapes, transition);
+            if seen.insert(key(&transition.next, &tapes)) {
+                children.push(Configuration { state: transition.next.clone(), tapes, parent: Some(index), transition: Some(choice), steps: current.steps + 1 });
+            }
+        }
+        if configurations.len() + children.len() > max_configurations {
+            break Outcome::OutOfConfigurations;
+        }
+        for child in children {
+            queue.push_back(configurations.len());
+            configurations.push(child);
+        }
+    };
+
+    let mut path = Vec::new();
+    let mut at = Some(last);
+    while let Some(index) = at {
+        if let Some(transition) = configurations[index].transition {
+            path.push(table.transitions[transition].clone());
+        }
+        at = configurations[index].parent;
+    }
+    path.reverse();
+
+    let end = &configurations[last];
+    ExplorationReport { outcome, configurations: configurations.len(), state: end.state.clone(), path, tapes: end.tapes.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition_reader::TableReader;
+
+    // Guesses where an `aa` starts
+    const CONTAINS_AA: &str = "
+        q     a a right q
+        q     b b right q
+        q     a a right found
+        found a a right accept
+    ";
+
+    fn explore_input(table: &str, input: &str, max_configurations: usize) -> ExplorationReport {
+        let table = TableReader::new(table).read().unwrap();
+        explore(&table, vec![Tape::from_input(input)], 1_000, max_configurations)
+    }
+
+    #[test]
+    fn finds_every_choice() {
+        let table = TableReader::new(CONTAINS_AA).read().unwrap();
+        let choices = choices(&table);
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].transitions.iter().map(|transition| transition.next.as_str()).collect::<Vec<_>>(), ["q", "found"]);
+    }
+
+    #[test]
+    fn reports_the_path_to_an_accepting_branch() {
+        let report = explore_input(CONTAINS_AA, "baab", 1_000);
+        assert_eq!(report.outcome, Outcome::Accepted);
+        let path: Vec<String> = report.path.iter().map(|transition| transition.to_string()).collect();
+        assert_eq!(path, ["q b b right q", "q a a right found", "found a a right accept"]);
+    }
+
+    #[test]
+    fn rejects_when_every_branch_halts() {
+        assert_eq!(explore_input(CONTAINS_AA, "abab", 1_000).outcome, Outcome::Rejected);
+    }
+
+    #[test]
+    fn stops_at_the_configuration_budget() {
+        let wander = "q _ _ right q\nq _ _ left q";
+        let report = explore_input(wander, "", 50);
+        assert_eq!(report.outcome, Outcome::OutOfConfigurations);
+        assert!(report.configurations <= 50);
+    }
+}
@@ -0,0 +1,413 @@
+use crate::diagnostics::{Diagnostic, SourceFile};
+use crate::jflap;
+use crate::lexer_types::Span;
+use crate::simulator::{ACCEPT_STATES, REJECT_STATES};
+use crate::transition_types::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+// The table formats of other simulators that machines can be written in and read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Tm, //the README format
+    TuringMachineSimulator, //turingmachinesimulator.com
+    Morphett, //morphett.info/turing
+    Jflap,
+}
+
+impl Format {
+    pub fn parse(text: &str) -> Option<Format> {
+        match text {
+            "tm" => Some(Format::Tm),
+            "tmsim" => Some(Format::TuringMachineSimulator),
+            "morphett" => Some(Format::Morphett),
+            "jflap" => Some(Format::Jflap),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatError {
+    pub message : String,
+    pub label : String,
+    pub span : Span,
+}
+
+impl FormatError {
+    pub fn new(message: &str, label: &str, span: Span) -> FormatError {
+        FormatError { message: message.to_string(), label: label.to_string(), span }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(&self.message, self.span).with_primary_label(&self.label)
+    }
+}
+
+// name is used where a format wants the machine to have one
+pub fn export(format: Format, table: &TransitionTable, name: &str) -> Result<String, String> {
+    match format {
+        Format::Tm => Ok(table.to_string()),
+        Format::TuringMachineSimulator => Ok(export_turing_machine_simulator(table, name)),
+        Format::Morphett => export_morphett(table),
+        Format::Jflap => Ok(jflap::export(table)),
+    }
+}
+
+// input holds the symbols the machine will be started on, which Morphett's wildcards have to cover as well
+pub fn import(format: Format, source: &SourceFile, input: &[String]) -> Result<TransitionTable, Vec<FormatError>> {
+    match format {
+        Format::Tm => unreachable!("the README format has its own reader"),
+        Format::TuringMachineSimulator => import_turing_machine_simulator(source),
+        Format::Morphett => import_morphett(source, input),
+        Format::Jflap => jflap::import(source),
+    }
+}
+
+// Other simulators accept in a set of final states and stop there, here that is the state named accept
+pub fn accept_in(table: TransitionTable, finals: &HashSet<String>) -> TransitionTable {
+    let accept = ACCEPT_STATES[0];
+    let rename = |state: &str| if finals.contains(state) { accept.to_string() } else { state.to_string() };
+    let transitions = table.transitions.into_iter()
+        .filter(|transition| !finals.contains(&transition.state))
+        .map(|transition| Transition { next: rename(&transition.next), ..transition })
+        .collect();
+    TransitionTable { start: rename(&table.start), transitions }
+}
+
+// The lines of a file with their byte offset, comments cut off and surrounding whitespace trimmed
+fn lines<'a>(source: &'a SourceFile, comment: &str) -> Vec<(usize, &'a str)> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in source.content.split('\n') {
+        let code = line.find(comment).map(|end| &line[..end]).unwrap_or(line);
+        let trimmed = code.trim();
+        if !trimmed.is_empty() {
+            lines.push((start + (code.len() - code.trim_start().len()), trimmed));
+        }
+        start += line.len() + 1;
+    }
+    lines
+}
+
+pub fn span_of(offset: usize, text: &str) -> Span {
+    Span { start: offset, end: offset + text.len() }
+}
+
+pub fn at(transition: Transition, source: &SourceFile, offset: usize) -> Transition {
+    Transition { position: Some(source.position(offset)), ..transition }
+}
+
+// The accept states the table enters, the simulators that want a list of them only know these names
+fn accept_states(table: &TransitionTable) -> Vec<&'static str> {
+    let used = |name: &str| table.start == name || table.transitions.iter().any(|transition| transition.state == name || transition.next == name);
+    let states: Vec<&'static str> = ACCEPT_STATES.iter().copied().filter(|name| used(name)).collect();
+    if states.is_empty() { vec![ACCEPT_STATES[0]] } else { states }
+}
+
+// turingmachinesimulator.com:
+//
+// name: increment
+// init: q0
+// accept: accept
+//
+// q0,1
+// q0,1,>
+//
+// Every transition is a `state,read...` line followed by a `next,write...,move...` line, with < > and - as moves
+fn export_turing_machine_simulator(table: &TransitionTable, name: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "name: {}", name);
+    let _ = writeln!(out, "init: {}", table.start);
+    let _ = writeln!(out, "accept: {}", accept_states(table).join(", "));
+    for transition in &table.transitions {
+        let moves: Vec<&str> = transition.directions.iter().map(|direction| match direction {
+            Direction::Left => "<",
+            Direction::Right => ">",
+            Direction::Stay => "-",
+        }).collect();
+        let _ = writeln!(out, "\n{},{}", transition.state, transition.read.join(","));
+        let _ = writeln!(out, "{},{},{}", transition.next, transition.write.join(","), moves.join(","));
+    }
+    out
+}
+
+fn import_turing_machine_simulator(source: &SourceFile) -> Result<TransitionTable, Vec<FormatError>> {
+    let mut table = TransitionTable::default();
+    let mut finals = HashSet::new();
+    let mut errors = Vec::new();
+    let mut condition: Option<(usize, &str, Vec<&str>)> = None;
+
+    for (offset, line) in lines(source, "//") {
+        match line.split_once(':').map(|(key, value)| (key.trim(), value.trim())) {
+            Some(("name", _)) => continue,
+            Some(("init", state)) => {
+                table.start = state.to_string();
+                continue;
+            }
+            Some(("accept", states)) => {
+                finals.extend(states.split(',').map(|state| state.trim().to_string()));
+                continue;
+            }
+            _ => {}
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let Some((condition_offset, condition_line, read)) = condition.take() else {
+            condition = Some((offset, line, fields));
+            continue;
+        };
+
+        // like in the README format the first transition decides how many tapes the table has
+        let tapes = read.len() - 1;
+        let expected = table.transitions.first().map(|transition| transition.tapes()).unwrap_or(tapes.max(1));
+        if tapes != expected {
+            let kind = TableErrorKind::WrongTapeCount { expected, found: tapes };
+            errors.push(FormatError::new(&kind.message(), "wrong number of tapes", span_of(condition_offset, condition_line)));
+            continue;
+        }
+        // `next,write...,move...` has a write and a move for every symbol `state,read...` reads
+        if fields.len() != 1 + 2 * tapes {
+            let message = format!("expected the next state, {} symbols and {} moves", tapes, tapes);
+            errors.push(FormatError::new(&message, &format!("found {} fields", fields.len()), span_of(offset, line)));
+            continue;
+        }
+        let mut directions = Vec::new();
+        for direction in &fields[1 + tapes..] {
+            match *direction {
+                "<" => directions.push(Direction::Left),
+                ">" => directions.push(Direction::Right),
+                "-" => directions.push(Direction::Stay),
+                _ => errors.push(FormatError::new(&format!("invalid move '{}'", direction), "expected <, > or -", span_of(offset, line))),
+            }
+        }
+        if directions.len() == tapes {
+            let symbols = |fields: &[&str]| fields.iter().map(|symbol| symbol.to_string()).collect();
+            let transition = Transition::multi(read[0], symbols(&read[1..]), symbols(&fields[1..1 + tapes]), directions, fields[0]);
+            table.transitions.push(at(transition, source, condition_offset));
+        }
+    }
+
+    if let Some((offset, _, _)) = condition {
+        errors.push(FormatError::new("transition without its second line", "expected `next,write...,move...` after this", Span { start: offset, end: offset + 1 }));
+    }
+    if table.start.is_empty() {
+        errors.push(FormatError::new("missing 'init:' line", "the start state is never given", Span { start: 0, end: 0 }));
+    }
+    if errors.is_empty() {
+        Ok(accept_in(table, &finals))
+    } else {
+        Err(errors)
+    }
+}
+
+// morphett.info/turing: `<state> <read> <write> <l|r|*> <next>` with `;` comments and 0 as start state.
+// `*` reads any symbol a state has no rule of its own for, writes what was read and as next state stays put.
+// Here it becomes a transition for every symbol in the file or the input.
+// The machine stops in any state whose name starts with halt
+const MORPHETT_START: &str = "0";
+const MORPHETT_ACCEPT: &str = "halt-accept";
+const MORPHETT_REJECT: &str = "halt-reject";
+const WILDCARD: &str = "*";
+
+fn export_morphett(table: &TransitionTable) -> Result<String, String> {
+    if table.tapes() > 1 {
+        return Err("Morphett's simulator has a single tape, use --single-tape to reduce the table first".to_string());
+    }
+    // the start state has to be 0, and only the halting states may start with halt
+    let rename = |state: &str| -> String {
+        if state == table.start {
+            MORPHETT_START.to_string()
+        } else if ACCEPT_STATES.contains(&state) {
+            MORPHETT_ACCEPT.to_string()
+        } else if REJECT_STATES.contains(&state) {
+            MORPHETT_REJECT.to_string()
+        } else if state == MORPHETT_START || state.starts_with("halt") {
+            format!("_{}", state)
+        } else {
+            state.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    for transition in &table.transitions {
+        let direction = match transition.directions[0] {
+            Direction::Left => "l",
+            Direction::Right => "r",
+            Direction::Stay => WILDCARD,
+        };
+        let _ = writeln!(out, "{} {} {} {} {}", rename(&transition.state), transition.read[0], transition.write[0], direction, rename(&transition.next));
+    }
+    Ok(out)
+}
+
+struct Rule<'a> {
+    offset : usize,
+    fields : Vec<&'a str>,
+    direction : Direction,
+}
+
+fn import_morphett(source: &SourceFile, input: &[String]) -> Result<TransitionTable, Vec<FormatError>> {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (offset, line) in lines(source, ";") {
+        let mut fields: Vec<&str> = line.split_whitespace().collect();
+        // a trailing ! sets a breakpoint
+        if fields.len() == 6 && fields[5] == "!" {
+            fields.pop();
+        }
+        if fields.len() != 5 {
+            let message = format!("expected 5 fields in a transition but found {}", fields.len());
+            errors.push(FormatError::new(&message, "malformed transition", span_of(offset, line)));
+            continue;
+        }
+        let direction = match fields[3].to_lowercase().as_str() {
+            "l" => Direction::Left,
+            "r" => Direction::Right,
+            "*" => Direction::Stay,
+            _ => {
+                errors.push(FormatError::new(&format!("invalid direction '{}'", fields[3]), "expected l, r or *", span_of(offset, line)));
+                continue;
+            }
+        };
+        rules.push(Rule { offset, fields, direction });
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let concrete = |field: &&str| *field != WILDCARD;
+    let symbols: BTreeSet<&str> = std::iter::once(BLANK)
+        .chain(rules.iter().flat_map(|rule| [rule.fields[1], rule.fields[2]]).filter(concrete))
+        .chain(input.iter().map(String::as_str))
+        .collect();
+    let mut states: Vec<&str> = Vec::new();
+    for state in rules.iter().flat_map(|rule| [rule.fields[0], rule.fields[4]]).filter(concrete) {
+        if !states.contains(&state) {
+            states.push(state);
+        }
+    }
+
+    // a rule for the exact state and symbol wins over a wildcard symbol, which wins over a wildcard state
+    let mut lookup: HashMap<(&str, &str), &Rule> = HashMap::new();
+    for rule in &rules {
+        lookup.entry((rule.fields[0], rule.fields[1])).or_insert(rule);
+    }
+    let mut table = TransitionTable { start: MORPHETT_START.to_string(), transitions: Vec::new() };
+    for &state in states.iter().filter(|state| !state.starts_with("halt")) {
+        for &symbol in &symbols {
+            let rule = [(state, symbol), (state, WILDCARD), (WILDCARD, symbol), (WILDCARD, WILDCARD)].iter()
+                .find_map(|key| lookup.get(key));
+            if let Some(rule) = rule {
+                let write = if rule.fields[2] == WILDCARD { symbol } else { rule.fields[2] };
+                let next = if rule.fields[4] == WILDCARD { state } else { rule.fields[4] };
+                table.transitions.push(at(Transition::new(state, symbol, write, rule.direction, next), source, rule.offset));
+            }
+        }
+    }
+
+    // a plain halt is how a Morphett machine says it is done
+    let accepting: HashSet<String> = ["halt", MORPHETT_ACCEPT].iter().map(|state| state.to_string()).collect();
+    let rejecting = REJECT_STATES[0];
+    let table = accept_in(table, &accepting);
+    let transitions = table.transitions.into_iter()
+        .map(|transition| if transition.next == MORPHETT_REJECT { Transition { next: rejecting.to_string(), ..transition } } else { transition })
+        .collect();
+    Ok(TransitionTable { start: table.start, transitions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Halt, Machine, Tape};
+    use crate::transition_reader::TableReader;
+
+    const INCREMENT: &str = "
+        right 0 0 right right
+        right 1 1 right right
+        right _ _ left  carry
+        carry 1 0 left  carry
+        carry 0 1 stay  accept
+        carry _ 1 stay  accept
+    ";
+
+    fn run(table: &TransitionTable, input: &str) -> (Option<Halt>, String) {
+        let report = Machine::new(table, vec![Tape::from_input(input)]).run(1_000);
+        (report.halt, report.tapes[0].to_string())
+    }
+
+    fn round_trip(format: Format) -> TransitionTable {
+        let table = TableReader::new(INCREMENT).read().unwrap();
+        let exported = export(format, &table, "increment").unwrap();
+        import(format, &SourceFile::new("exported", &exported), &[]).unwrap()
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for format in [Format::TuringMachineSimulator, Format::Morphett, Format::Jflap] {
+            let table = round_trip(format);
+            assert_eq!(run(&table, "1011"), (Some(Halt::Accept), "1100".to_string()), "{:?}", format);
+            assert_eq!(run(&table, "111"), (Some(Halt::Accept), "1000".to_string()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn turing_machine_simulator_reads_multiple_tapes() {
+        let source = "
+            name: copy
+            init: q0
+            accept: done
+
+            // copies the input onto the second tape
+            q0,1,_
+            q0,1,1,>,>
+
+            q0,_,_
+            done,_,_,-,-
+        ";
+        let table = import(Format::TuringMachineSimulator, &SourceFile::new("copy", source), &[]).unwrap();
+        assert_eq!(table.to_string(), "q0 1,_ 1,1 right,right q0\nq0 _,_ _,_ stay,stay accept\n");
+    }
+
+    #[test]
+    fn turing_machine_simulator_keeps_the_tape_count() {
+        let import = |source: &str| import(Format::TuringMachineSimulator, &SourceFile::new("bad", source), &[]).unwrap_err();
+        let errors = import("init: q0\nq0,1,1\nq1,1,1,>,>\nq1,1\ndone,1,>");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "expected 2 tapes but found 1");
+        assert_eq!(errors[0].span, Span { start: 27, end: 31 });
+
+        // a condition has to read at least one symbol
+        let errors = import("init: q0\nq0\ndone");
+        assert_eq!(errors.iter().map(|error| error.message.as_str()).collect::<Vec<_>>(), ["expected 1 tapes but found 0"]);
+        assert_eq!(errors[0].span, Span { start: 9, end: 11 });
+    }
+
+    #[test]
+    fn turing_machine_simulator_lists_every_accept_state() {
+        let table = TableReader::new("q 1 1 right ACCEPT\nq _ _ stay accept\nq 0 0 stay reject").read().unwrap();
+        let exported = export(Format::TuringMachineSimulator, &table, "both").unwrap();
+        assert!(exported.starts_with("name: both\ninit: q\naccept: accept, ACCEPT\n"), "{}", exported);
+        let table = TableReader::new("q 1 1 right ACCEPT").read().unwrap();
+        let exported = export(Format::TuringMachineSimulator, &table, "upper").unwrap();
+        assert!(exported.contains("\naccept: ACCEPT\n"), "{}", exported);
+    }
+
+    #[test]
+    fn morphett_wildcards_are_expanded() {
+        let source = "
+            ; skips to the end and marks it
+            0 * * r 0
+            0 _ x * halt
+        ";
+        let table = import(Format::Morphett, &SourceFile::new("mark", source), &["a".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(table.to_string(), "0 _ x stay accept\n0 a a right 0\n0 b b right 0\n0 x x right 0\n");
+        assert_eq!(run(&table, "ab"), (Some(Halt::Accept), "abx".to_string()));
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let errors = import(Format::Morphett, &SourceFile::new("bad", "0 a b up 1\n0 a\n"), &[]).unwrap_err();
+        assert_eq!(errors.iter().map(|error| error.span.start).collect::<Vec<_>>(), [0, 11]);
+    }
+}
@@ -0,0 +1,185 @@
+
+use crate::lexer_types::*;
+use crate::transition_reader::split_comment;
+
+const INDENT: &str = "    ";
+
+// The source text of a token, string literals get their quotes back
+fn raw_text<'a>(source: &'a str, token: &Token) -> &'a str {
+    match token.kind {
+        TokenKind::StringLiteral => &source[token.span.start - 1..token.span.end + 1],
+        _ => &source[token.span.start..token.span.end],
+    }
+}
+
+fn is_unary_position(before: Option<&Token>) -> bool {
+    match before {
+        None => true,
+        Some(token) => matches!(token.kind, TokenKind::Operator | TokenKind::OpenParen | TokenKind::Comma
+            | TokenKind::Keyword | TokenKind::LineBreak | TokenKind::OpenParenCurly),
+    }
+}
+
+// Whether a space goes between the previous token and the current one, before_previous decides if a `-` is unary
+fn needs_space(before_previous: Option<&Token>, previous: &Token, current: &Token) -> bool {
+    match current.kind {
+        TokenKind::LineBreak | TokenKind::Comma | TokenKind::CloseParen | TokenKind::Colon => return false,
+        TokenKind::OpenParen if previous.kind == TokenKind::Symbol => return false,
+        TokenKind::Ellipsis if previous.kind == TokenKind::OpenParenCurly => return false,
+        TokenKind::CloseParenCurly if previous.kind == TokenKind::Ellipsis => return false,
+        TokenKind::Operator if (current.text == "++" || current.text == "--") && previous.kind == TokenKind::Symbol => return false,
+        _ => {}
+    }
+    match previous.kind {
+        TokenKind::OpenParen => false,
+        TokenKind::Operator if previous.text == "-" && is_unary_position(before_previous) => false,
+        _ => true,
+    }
+}
+
+// Re-indents a program by brace depth and normalises the spacing between tokens
+// comments are kept and runs of blank lines are collapsed into one
+pub fn format_source(source: &str, tokens: &[Token]) -> String {
+    let mut lines: Vec<Vec<&Token>> = vec![Vec::new()];
+    for token in tokens {
+        match token.kind {
+            TokenKind::EOF => break,
+            TokenKind::NewLine => lines.push(Vec::new()),
+            _ => lines.last_mut().expect("there is always a line").push(token),
+        }
+    }
+
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    let mut pending_blank = false;
+
+    for line in lines {
+        if line.is_empty() {
+            pending_blank = !out.is_empty();
+            continue;
+        }
+        if pending_blank {
+            out.push('\n');
+            pending_blank = false;
+        }
+
+        let closes_first = line[0].kind == TokenKind::CloseParenCurly;
+        out.push_str(&INDENT.repeat(if closes_first { depth.saturating_sub(1) } else { depth }));
+
+        for (index, token) in line.iter().enumerate() {
+            if index > 0 {
+                let before_previous = if index > 1 { Some(line[index - 2]) } else { None };
+                if token.kind == TokenKind::Comment || needs_space(before_previous, line[index - 1], token) {
+                    out.push(' ');
+                }
+            }
+            out.push_str(raw_text(source, token).trim_end());
+            match token.kind {
+                TokenKind::OpenParenCurly => depth += 1,
+                TokenKind::CloseParenCurly => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Aligns the columns of every transition, comment lines are kept as they are
+pub fn format_table(content: &str) -> String {
+    let lines: Vec<(Vec<&str>, Option<&str>)> = content.lines()
+        .map(|line| {
+            let (code, comment) = split_comment(line);
+            (code.split_whitespace().collect(), comment.map(|comment| comment.trim_end()))
+        })
+        .collect();
+
+    let mut widths = [0; 4];
+    for (fields, _) in &lines {
+        for (width, field) in widths.iter_mut().zip(fields.iter()) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let mut pending_blank = false;
+    for (fields, comment) in lines {
+        if fields.is_empty() && comment.is_none() {
+            pending_blank = !out.is_empty();
+            continue;
+        }
+        if pending_blank {
+            out.push('\n');
+            pending_blank = false;
+        }
+
+        let mut line = String::new();
+        for (index, field) in fields.iter().enumerate() {
+            match widths.get(index) {
+                Some(&width) if index + 1 < fields.len() => line.push_str(&format!("{:<width$} ", field, width = width)),
+                _ => line.push_str(field),
+            }
+        }
+        if let Some(comment) = comment {
+            if !line.is_empty() {
+                line.push_str("  ");
+            }
+            line.push_str(comment);
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn format(source: &str) -> String {
+        format_source(source, &Lexer::new(source).tokenize().unwrap())
+    }
+
+    #[test]
+    fn spaces_tokens_and_keeps_unary_minus_tight() {
+        assert_eq!(format("let x=-1+ 2*( -y);"), "let x = -1 + 2 * (-y);\n");
+        assert_eq!(format("x=a - -b;f( 1 ,2 );x++ ;"), "x = a - -b; f(1, 2); x++;\n");
+        assert_eq!(format("define g(  ){ ... }"), "define g() {...}\n");
+    }
+
+    #[test]
+    fn indents_blocks_and_collapses_blank_lines() {
+        let source = "define f(a) {\nif a {\n\n\n\t\ta--;   # down\n}\n      else {\nwhile a{a++;}\n}\n}\n\n\nf(1);\n";
+        let formatted = format(source);
+        assert_eq!(formatted, "\
+define f(a) {
+    if a {
+
+        a--; # down
+    }
+    else {
+        while a { a++; }
+    }
+}
+
+f(1);
+");
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn aligns_table_columns() {
+        let table = "// increment\nright 1 1 right right\n\n\n\nright _ _ left carry   // turn around\ncarry 1 0 left carry\ncarry _ 1 stay accept\n";
+        let formatted = format_table(table);
+        assert_eq!(formatted, "\
+// increment
+right 1 1 right right
+
+right _ _ left  carry  // turn around
+carry 1 0 left  carry
+carry _ 1 stay  accept
+");
+        assert_eq!(format_table(&formatted), formatted);
+    }
+}
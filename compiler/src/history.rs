@@ -0,0 +1,151 @@
+use crate::dense::{DenseTable, RingTape};
+use std::collections::VecDeque;
+
+// Checkpoints start out this many steps apart, the spacing doubles whenever there would be more than MAX_CHECKPOINTS
+const CHECKPOINT_INTERVAL: usize = 1_000;
+const MAX_CHECKPOINTS: usize = 256;
+// How many of the latest steps can be taken back one by one
+const MAX_UNDO: usize = 100_000;
+
+// What a step changed, enough to take it back
+struct Undo {
+    state : u32,
+    transition : u32,
+    symbols : Vec<u32>, //under every head before the step wrote
+}
+
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub steps : usize,
+    pub state : u32,
+    pub tapes : Vec<RingTape>,
+}
+
+// Lets a machine go back to earlier steps in bounded memory. The latest steps are undone from a log of what they
+// overwrote, anything older is replayed from the closest checkpoint before it. The checkpoint of the first step
+// is never thinned out, so every step stays reachable, replaying at most one checkpoint interval
+pub struct History {
+    undo : VecDeque<Undo>,
+    checkpoints : Vec<Checkpoint>,
+    interval : usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History { undo: VecDeque::new(), checkpoints: Vec::new(), interval: CHECKPOINT_INTERVAL }
+    }
+
+    // Called with the configuration before every step and the transition it takes
+    pub fn record(&mut self, steps: usize, state: u32, tapes: &[RingTape], transition: u32) {
+        // after going back, the steps up to the latest checkpoint are replayed and already have theirs
+        let first = self.checkpoints.first().map(|checkpoint| checkpoint.steps).unwrap_or(steps);
+        let due = (steps - first).is_multiple_of(self.interval);
+        if due && self.checkpoints.last().is_none_or(|last| last.steps < steps) {
+            self.checkpoints.push(Checkpoint { steps, state, tapes: tapes.to_vec() });
+            if self.checkpoints.len() > MAX_CHECKPOINTS {
+                let mut index = 0;
+                self.checkpoints.retain(|_| {
+                    index += 1;
+                    index % 2 == 1
+                });
+                self.interval *= 2;
+            }
+        }
+
+        self.undo.push_back(Undo { state, transition, symbols: tapes.iter().map(RingTape::read).collect() });
+        if self.undo.len() > MAX_UNDO {
+            self.undo.pop_front();
+        }
+    }
+
+    // How many steps undo can take back
+    pub fn undoable(&self) -> usize {
+        self.undo.len()
+    }
+
+    // Takes back the latest step that is still in the log
+    pub fn undo(&mut self, state: &mut u32, tapes: &mut [RingTape], table: &DenseTable) {
+        let Some(undo) = self.undo.pop_back() else {
+            return;
+        };
+        let first = undo.transition as usize * tapes.len();
+        for (index, (tape, symbol)) in tapes.iter_mut().zip(undo.symbols).enumerate() {
+            tape.shift(-table.moves[first + index]);
+            tape.write(symbol);
+        }
+        *state = undo.state;
+    }
+
+    // The latest checkpoint at or before the step. The undo log only holds later steps, so it is dropped
+    pub fn restore(&mut self, step: usize) -> Option<Checkpoint> {
+        let checkpoint = self.checkpoints.iter().rev().find(|checkpoint| checkpoint.steps <= step)?.clone();
+        self.undo.clear();
+        Some(checkpoint)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Machine, Tape};
+    use crate::transition_reader::TableReader;
+
+    // Counts up in binary forever, the lowest bit on the right
+    const COUNTER: &str = "
+        right  0 0 right right
+        right  1 1 right right
+        right  _ _ left  carry
+        carry  1 0 left  carry
+        carry  0 1 stay  rewind
+        carry  _ 1 stay  rewind
+        rewind 0 0 left  rewind
+        rewind 1 1 left  rewind
+        rewind _ _ right right
+    ";
+
+    type Configuration = (String, Vec<(Option<(i64, i64)>, String, i64)>);
+
+    fn configuration(machine: &Machine) -> Configuration {
+        let tapes = machine.tapes().iter().map(|tape| (tape.bounds(), tape.to_string(), tape.head())).collect();
+        (machine.state().to_string(), tapes)
+    }
+
+    fn counter() -> Machine {
+        Machine::new(&TableReader::new(COUNTER).read().unwrap(), vec![Tape::from_input("0")])
+    }
+
+    #[test]
+    fn every_earlier_step_can_be_reached() {
+        let mut machine = counter();
+        machine.keep_history();
+        machine.run(300_000);
+        // the first jump is undone step by step, the later ones replay from a checkpoint
+        for step in [299_990, 250_000, 123_457, 1, 0, 200_001] {
+            machine.jump(step).unwrap();
+            let mut fresh = counter();
+            fresh.run(step);
+            assert_eq!(configuration(&machine), configuration(&fresh), "step {}", step);
+        }
+        machine.step_back(1).unwrap();
+        assert_eq!(machine.steps(), 200_000);
+        assert!(machine.step_back(200_001).is_err());
+    }
+
+    #[test]
+    fn memory_stays_bounded() {
+        let mut history = History::new();
+        let tapes = vec![RingTape::new(0, &[1], 0)];
+        for step in 0..1_000_000 {
+            history.record(step, 0, &tapes, 0);
+        }
+        assert!(history.checkpoints.len() <= MAX_CHECKPOINTS);
+        assert_eq!(history.checkpoints[0].steps, 0);
+        assert_eq!(history.undoable(), MAX_UNDO);
+    }
+}
@@ -0,0 +1,12 @@
+# Description of a Turing Machine to increment a binary number
+# Transition format: <current-state> <read-symbol> <write-symbol> <move-direction> <next-state>
+
+// Initial state
+GO_TOTAL_RIGHT 1 1 right GO_TOTAL_RIGHT
+GO_TOTAL_RIGHT 0 0 right GO_TOTAL_RIGHT
+GO_TOTAL_RIGHT _ _ left  FIX_LEAST_SIGNIFICANT_BIT
+
+// state for carying over
+FIX_LEAST_SIGNIFICANT_BIT 0 1 stay DONE
+FIX_LEAST_SIGNIFICANT_BIT 1 0 left FIX_LEAST_SIGNIFICANT_BIT
+FIX_LEAST_SIGNIFICANT_BIT _ 1 stay DONE
@@ -0,0 +1,206 @@
+use crate::diagnostics::Diagnostic;
+use crate::lexer_types::Location;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct RegionId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct BlockId(pub usize);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+// How a region writes its value on the tape, see codegen_types::digits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+pub enum Encoding {
+    #[default]
+    Unary,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+    Decimal,
+}
+
+impl Encoding {
+    pub fn parse(text: &str) -> Option<Encoding> {
+        match text {
+            "unary" => Some(Encoding::Unary),
+            "binary-le" => Some(Encoding::BinaryLittleEndian),
+            "binary-be" => Some(Encoding::BinaryBigEndian),
+            "decimal" => Some(Encoding::Decimal),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Encoding::Unary => "unary",
+            Encoding::BinaryLittleEndian => "binary-le",
+            Encoding::BinaryBigEndian => "binary-be",
+            Encoding::Decimal => "decimal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The encoding of every variable, anything not listed (including literals and temporaries) uses the default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Encodings {
+    pub default : Encoding,
+    pub variables : HashMap<String, Encoding>,
+}
+
+impl Encodings {
+    pub fn of(&self, variable: &str) -> Encoding {
+        self.variables.get(variable).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum RegionKind {
+    Variable,
+    Constant(usize),
+    Temporary,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Region {
+    pub name : String,
+    pub kind : RegionKind,
+    pub encoding : Encoding,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            RegionKind::Variable => write!(f, "{}", self.name),
+            RegionKind::Constant(value) => write!(f, "#{}", value),
+            RegionKind::Temporary => write!(f, "%{}", self.name),
+        }
+    }
+}
+
+// The regions in the order they sit on the tape, a RegionId indexes into it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Layout {
+    pub regions : Vec<Region>,
+}
+
+impl Layout {
+    pub fn push(&mut self, name: &str, kind: RegionKind, encoding: Encoding) -> RegionId {
+        self.regions.push(Region { name: name.to_string(), kind, encoding });
+        RegionId(self.regions.len() - 1)
+    }
+
+    pub fn get(&self, id: RegionId) -> &Region {
+        &self.regions[id.0]
+    }
+}
+
+// Every instruction but MoveTo works on the region the head is on, which the MoveTo in front of it names.
+// The head is back on that region's marker once the instruction is done
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Instruction {
+    MoveTo(RegionId),
+    Increment(RegionId),
+    Decrement(RegionId), //stops at 0
+    Clear(RegionId),
+    Copy { from: RegionId, to: RegionId },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Terminator {
+    Jump(BlockId),
+    BranchZero { region: RegionId, zero: BlockId, nonzero: BlockId },
+    Halt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BasicBlock {
+    pub id : BlockId,
+    pub instructions : Vec<Instruction>,
+    pub terminator : Terminator,
+}
+
+// Execution starts in the first block with the head on the first region
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IrProgram {
+    pub layout : Layout,
+    pub blocks : Vec<BasicBlock>,
+}
+
+// regions:
+//     a   variable    unary
+//     #1  constant 1  unary
+// bb0:
+//     move_to a
+//     inc a
+//     halt
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |id: RegionId| self.layout.get(id).to_string();
+        let width = self.layout.regions.iter().map(|region| region.to_string().len()).max().unwrap_or(0);
+
+        let kind = |region: &Region| match region.kind {
+            RegionKind::Variable => "variable".to_string(),
+            RegionKind::Constant(value) => format!("constant {}", value),
+            RegionKind::Temporary => "temporary".to_string(),
+        };
+        let kind_width = self.layout.regions.iter().map(|region| kind(region).len()).max().unwrap_or(0);
+
+        writeln!(f, "regions:")?;
+        for region in &self.layout.regions {
+            writeln!(f, "    {:<width$}  {:<kind_width$}  {}", region.to_string(), kind(region), region.encoding,
+                width = width, kind_width = kind_width)?;
+        }
+
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.id)?;
+            for instruction in &block.instructions {
+                match *instruction {
+                    Instruction::MoveTo(region) => writeln!(f, "    move_to {}", name(region))?,
+                    Instruction::Increment(region) => writeln!(f, "    inc {}", name(region))?,
+                    Instruction::Decrement(region) => writeln!(f, "    dec {}", name(region))?,
+                    Instruction::Clear(region) => writeln!(f, "    clear {}", name(region))?,
+                    Instruction::Copy { from, to } => writeln!(f, "    copy {} -> {}", name(from), name(to))?,
+                }
+            }
+            match block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump {}", target)?,
+                Terminator::BranchZero { region, zero, nonzero } => writeln!(f, "    if_zero {} {} else {}", name(region), zero, nonzero)?,
+                Terminator::Halt => writeln!(f, "    halt")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoweringError {
+    pub message : String,
+    pub label : String,
+    pub location : Location,
+}
+
+impl LoweringError {
+    pub fn new(message: &str, label: &str, location: Location) -> LoweringError {
+        LoweringError { message: message.to_string(), label: label.to_string(), location }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(&self.message, self.location.span).with_primary_label(&self.label)
+    }
+}
+
+impl fmt::Display for LoweringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at row {}, column {}", self.message, self.location.start.row + 1, self.location.start.col + 1)
+    }
+}
@@ -0,0 +1,435 @@
+use crate::diagnostics::SourceFile;
+use crate::formats::{accept_in, at, FormatError};
+use crate::lexer_types::Span;
+use crate::simulator::ACCEPT_STATES;
+use crate::transition_types::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+// JFLAP's .jff files are XML:
+//
+// <structure>
+//   <type>turing</type>
+//   <tapes>2</tapes>                      only when there is more than one
+//   <automaton>
+//     <state id="0" name="q0"><x>100</x><y>100</y><initial/></state>
+//     <transition><from>0</from><to>1</to><read tape="1">a</read><write tape="1"/><move tape="1">R</move>...</transition>
+//   </automaton>
+// </structure>
+//
+// An empty read or write is the blank and a machine accepts by entering a final state
+const STATES_PER_ROW: usize = 8;
+const GRID: usize = 150;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// <read/> for the blank, <read tape="2">a</read> on a multi-tape table
+fn symbol_element(name: &str, tape: Option<usize>, symbol: &str) -> String {
+    let attribute = tape.map(|tape| format!(" tape=\"{}\"", tape + 1)).unwrap_or_default();
+    if symbol == BLANK {
+        format!("<{}{}/>", name, attribute)
+    } else {
+        format!("<{}{}>{}</{}>", name, attribute, escape(symbol), name)
+    }
+}
+
+pub fn export(table: &TransitionTable) -> String {
+    let mut states: Vec<&str> = vec![&table.start];
+    for transition in &table.transitions {
+        for state in [&transition.state, &transition.next] {
+            if !states.contains(&state.as_str()) {
+                states.push(state);
+            }
+        }
+    }
+    let ids: HashMap<&str, usize> = states.iter().enumerate().map(|(id, state)| (*state, id)).collect();
+    let tapes = table.tapes();
+    let tape = |index: usize| if tapes > 1 { Some(index) } else { None };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>");
+    let _ = writeln!(out, "<structure>");
+    let _ = writeln!(out, "\t<type>turing</type>");
+    if tapes > 1 {
+        let _ = writeln!(out, "\t<tapes>{}</tapes>", tapes);
+    }
+    let _ = writeln!(out, "\t<automaton>");
+    for (id, state) in states.iter().enumerate() {
+        let _ = writeln!(out, "\t\t<state id=\"{}\" name=\"{}\">", id, escape(state));
+        let _ = writeln!(out, "\t\t\t<x>{}.0</x>", GRID * (1 + id % STATES_PER_ROW));
+        let _ = writeln!(out, "\t\t\t<y>{}.0</y>", GRID * (1 + id / STATES_PER_ROW));
+        if id == 0 {
+            let _ = writeln!(out, "\t\t\t<initial/>");
+        }
+        if ACCEPT_STATES.contains(state) {
+            let _ = writeln!(out, "\t\t\t<final/>");
+        }
+        let _ = writeln!(out, "\t\t</state>");
+    }
+    for transition in &table.transitions {
+        let _ = writeln!(out, "\t\t<transition>");
+        let _ = writeln!(out, "\t\t\t<from>{}</from>", ids[transition.state.as_str()]);
+        let _ = writeln!(out, "\t\t\t<to>{}</to>", ids[transition.next.as_str()]);
+        for index in 0..tapes {
+            let direction = match transition.directions[index] {
+                Direction::Left => "L",
+                Direction::Right => "R",
+                Direction::Stay => "S",
+            };
+            let _ = writeln!(out, "\t\t\t{}", symbol_element("read", tape(index), &transition.read[index]));
+            let _ = writeln!(out, "\t\t\t{}", symbol_element("write", tape(index), &transition.write[index]));
+            let attribute = tape(index).map(|tape| format!(" tape=\"{}\"", tape + 1)).unwrap_or_default();
+            let _ = writeln!(out, "\t\t\t<move{}>{}</move>", attribute, direction);
+        }
+        let _ = writeln!(out, "\t\t</transition>");
+    }
+    let _ = writeln!(out, "\t</automaton>");
+    let _ = writeln!(out, "</structure>");
+    out
+}
+
+// Just enough XML for .jff files: elements, attributes, text and the predefined entities.
+// Comments, the prolog and doctypes are skipped
+#[derive(Debug)]
+struct Element {
+    name : String,
+    attributes : Vec<(String, String)>,
+    children : Vec<Element>,
+    text : String,
+    span : Span, //the start tag
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+struct XmlReader<'a> {
+    text : &'a str,
+    offset : usize,
+}
+
+impl<'a> XmlReader<'a> {
+    fn new(text: &'a str) -> XmlReader<'a> {
+        XmlReader { text, offset: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn error(&self, message: &str, label: &str) -> FormatError {
+        let end = self.rest().chars().next().map(|c| self.offset + c.len_utf8()).unwrap_or(self.offset);
+        FormatError::new(message, label, Span { start: self.offset, end })
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), FormatError> {
+        if self.rest().starts_with(token) {
+            self.offset += token.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", token), "malformed XML"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.offset = self.text.len() - self.rest().trim_start().len();
+    }
+
+    // Skips past the next `end`
+    fn skip_past(&mut self, end: &str) -> Result<(), FormatError> {
+        match self.rest().find(end) {
+            Some(found) => {
+                self.offset += found + end.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("missing '{}'", end), "never closed")),
+        }
+    }
+
+    // Whitespace, comments, processing instructions and doctypes
+    fn skip_misc(&mut self) -> Result<(), FormatError> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_document(&mut self) -> Result<Element, FormatError> {
+        self.skip_misc()?;
+        let root = self.read_element()?;
+        self.skip_misc()?;
+        if self.offset < self.text.len() {
+            return Err(self.error("content after the root element", "unexpected"));
+        }
+        Ok(root)
+    }
+
+    fn read_name(&mut self) -> Result<String, FormatError> {
+        let length = self.rest().find(|c: char| c.is_whitespace() || "/>=".contains(c)).unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a name", "malformed XML"));
+        }
+        let name = self.rest()[..length].to_string();
+        self.offset += length;
+        Ok(name)
+    }
+
+    fn decode(&self, text: &str, offset: usize) -> Result<String, FormatError> {
+        let mut decoded = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('&') {
+            decoded.push_str(&rest[..start]);
+            let end = rest[start..].find(';').map(|end| start + end);
+            let entity = end.map(|end| &rest[start + 1..end]).unwrap_or("");
+            let character = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            let (Some(character), Some(end)) = (character, end) else {
+                let at = offset + text.len() - rest.len() + start;
+                return Err(FormatError::new("unknown entity", "expected &lt; &gt; &amp; &quot; &apos; or a character reference", Span { start: at, end: at + 1 }));
+            };
+            decoded.push(character);
+            rest = &rest[end + 1..];
+        }
+        decoded.push_str(rest);
+        Ok(decoded)
+    }
+
+    fn read_element(&mut self) -> Result<Element, FormatError> {
+        let start = self.offset;
+        self.expect("<")?;
+        let name = self.read_name()?;
+        let mut attributes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") || self.rest().starts_with('>') {
+                break;
+            }
+            let key = self.read_name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.rest().starts_with('\'') { "'" } else { "\"" };
+            self.expect(quote)?;
+            let value_start = self.offset;
+            self.skip_past(quote)?;
+            let value = self.decode(&self.text[value_start..self.offset - 1], value_start)?;
+            attributes.push((key, value));
+        }
+
+        let closed = self.rest().starts_with("/>");
+        self.expect(if closed { "/>" } else { ">" })?;
+        let mut element = Element { name, attributes, children: Vec::new(), text: String::new(), span: Span { start, end: self.offset } };
+        if closed {
+            return Ok(element);
+        }
+
+        loop {
+            let text_start = self.offset;
+            let length = self.rest().find('<').unwrap_or(self.rest().len());
+            self.offset += length;
+            let text = self.decode(&self.text[text_start..self.offset], text_start)?;
+            element.text.push_str(&text);
+
+            if self.rest().is_empty() {
+                return Err(FormatError::new(&format!("'{}' is never closed", element.name), "opened here", element.span));
+            } else if self.rest().starts_with("</") {
+                self.offset += 2;
+                let closing = self.read_name()?;
+                if closing != element.name {
+                    return Err(self.error(&format!("'{}' closes '{}'", closing, element.name), "mismatched closing tag"));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            } else if self.rest().starts_with("<!--") || self.rest().starts_with("<?") {
+                self.skip_misc()?;
+            } else {
+                element.children.push(self.read_element()?);
+            }
+        }
+    }
+}
+
+// The text of a child element, trimmed
+fn child_text<'a>(element: &'a Element, name: &str, errors: &mut Vec<FormatError>) -> Option<&'a str> {
+    let child = element.child(name);
+    if child.is_none() {
+        errors.push(FormatError::new(&format!("<{}> without <{}>", element.name, name), &format!("missing <{}>", name), element.span));
+    }
+    child.map(|child| child.text.trim())
+}
+
+// The read, write or move elements of a transition in tape order, a missing tape attribute is the first tape
+fn per_tape<'a>(transition: &'a Element, name: &'a str, tapes: usize, errors: &mut Vec<FormatError>) -> Option<Vec<&'a Element>> {
+    let mut found: Vec<Option<&Element>> = vec![None; tapes];
+    for element in transition.children(name) {
+        let tape = element.attribute("tape").map(str::parse::<usize>).unwrap_or(Ok(1));
+        match tape {
+            Ok(tape) if (1..=tapes).contains(&tape) && found[tape - 1].is_none() => found[tape - 1] = Some(element),
+            _ => {
+                errors.push(FormatError::new(&format!("invalid tape for <{}>", name), &format!("expected a tape from 1 to {} used once", tapes), element.span));
+                return None;
+            }
+        }
+    }
+    let elements: Option<Vec<&Element>> = found.into_iter().collect();
+    if elements.is_none() {
+        errors.push(FormatError::new(&format!("expected a <{}> for each of the {} tapes", name, tapes), "in this transition", transition.span));
+    }
+    elements
+}
+
+pub fn import(source: &SourceFile) -> Result<TransitionTable, Vec<FormatError>> {
+    let root = XmlReader::new(&source.content).read_document().map_err(|error| vec![error])?;
+    let mut errors = Vec::new();
+    if root.name != "structure" {
+        return Err(vec![FormatError::new("not a JFLAP file", "expected <structure>", root.span)]);
+    }
+    match root.child("type") {
+        Some(kind) if kind.text.trim() == "turing" => {}
+        Some(kind) => return Err(vec![FormatError::new(&format!("a JFLAP {} is not a Turing machine", kind.text.trim()), "expected turing", kind.span)]),
+        None => return Err(vec![FormatError::new("not a JFLAP file", "missing <type>", root.span)]),
+    }
+    let tapes = match root.child("tapes") {
+        Some(element) => match element.text.trim().parse::<usize>() {
+            Ok(tapes) if tapes > 0 => tapes,
+            _ => return Err(vec![FormatError::new("invalid number of tapes", "expected a positive number", element.span)]),
+        },
+        None => 1,
+    };
+    // JFLAP 6 puts the states straight into the structure
+    let automaton = root.child("automaton").unwrap_or(&root);
+    if let Some(block) = automaton.child("block") {
+        return Err(vec![FormatError::new("building blocks aren't supported", "expected plain states", block.span)]);
+    }
+
+    let mut names: HashMap<&str, String> = HashMap::new();
+    let mut used = HashSet::new();
+    let mut start = None;
+    let mut finals = HashSet::new();
+    for state in automaton.children("state") {
+        let Some(id) = state.attribute("id") else {
+            errors.push(FormatError::new("<state> without an id", "missing id", state.span));
+            continue;
+        };
+        // names are only labels in JFLAP, two states may share one
+        let mut name = state.attribute("name").map(str::to_string).unwrap_or_else(|| format!("q{}", id));
+        if !used.insert(name.clone()) {
+            name = format!("{}_{}", name, id);
+            used.insert(name.clone());
+        }
+        if state.child("initial").is_some() {
+            start = Some(name.clone());
+        }
+        if state.child("final").is_some() {
+            finals.insert(name.clone());
+        }
+        names.insert(id, name);
+    }
+
+    let mut table = TransitionTable { start: start.unwrap_or_default(), transitions: Vec::new() };
+    for transition in automaton.children("transition") {
+        let state = |name: &str, errors: &mut Vec<FormatError>| -> Option<String> {
+            let id = child_text(transition, name, errors)?;
+            let state = names.get(id).cloned();
+            if state.is_none() {
+                errors.push(FormatError::new(&format!("no state with id '{}'", id), &format!("in this <{}>", name), transition.child(name).map(|child| child.span).unwrap_or(transition.span)));
+            }
+            state
+        };
+        let (from, to) = (state("from", &mut errors), state("to", &mut errors));
+        let read = per_tape(transition, "read", tapes, &mut errors);
+        let write = per_tape(transition, "write", tapes, &mut errors);
+        let moves = per_tape(transition, "move", tapes, &mut errors);
+        let (Some(from), Some(to), Some(read), Some(write), Some(moves)) = (from, to, read, write, moves) else {
+            continue;
+        };
+
+        let symbols = |elements: &[&Element]| -> Vec<String> {
+            elements.iter().map(|element| match element.text.trim() {
+                "" => BLANK.to_string(),
+                symbol => symbol.to_string(),
+            }).collect()
+        };
+        let mut directions = Vec::new();
+        for element in &moves {
+            match element.text.trim() {
+                "L" => directions.push(Direction::Left),
+                "R" => directions.push(Direction::Right),
+                "S" => directions.push(Direction::Stay),
+                other => errors.push(FormatError::new(&format!("invalid move '{}'", other), "expected L, R or S", element.span)),
+            }
+        }
+        if directions.len() == tapes {
+            let transition_at = Transition::multi(&from, symbols(&read), symbols(&write), directions, &to);
+            table.transitions.push(at(transition_at, source, transition.span.start));
+        }
+    }
+
+    if table.start.is_empty() {
+        errors.push(FormatError::new("no initial state", "expected a state with <initial/>", automaton.span));
+    }
+    if errors.is_empty() {
+        Ok(accept_in(table, &finals))
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_jflap_files() {
+        let source = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?><!--Created with JFLAP 7.1.--><structure>
+            <type>turing</type>
+            <automaton>
+                <state id="0" name="q0"><x>60.0</x><y>80.0</y><initial/></state>
+                <state id="1" name="q1"><x>160.0</x><y>80.0</y><final/></state>
+                <transition><from>0</from><to>0</to><read>a</read><write>&lt;</write><move>R</move></transition>
+                <transition><from>0</from><to>1</to><read/><write/><move>S</move></transition>
+            </automaton>
+        </structure>"#;
+        let table = import(&SourceFile::new("test.jff", source)).unwrap();
+        assert_eq!(table.to_string(), "q0 a < right q0\nq0 _ _ stay accept\n");
+        assert_eq!(table.transitions[0].position.map(|position| position.row), Some(5));
+    }
+
+    #[test]
+    fn rejects_other_automata() {
+        let source = "<structure><type>fa</type><automaton/></structure>";
+        let errors = import(&SourceFile::new("test.jff", source)).unwrap_err();
+        assert_eq!(errors[0].message, "a JFLAP fa is not a Turing machine");
+        assert_eq!(errors[0].span.start, 11);
+    }
+}
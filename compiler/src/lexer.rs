@@ -1,33 +1,36 @@
+
 use crate::lexer_types::*;
 use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::CharIndices;
+use unicode_xid::UnicodeXID;
 
-pub struct Lexer {
-    content: String,
-    content_length: usize,
-    cursor : usize, //absolute
+pub struct Lexer<'a> {
+    content: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    cursor : usize, //absolute byte offset of the next char
     line : usize, 
-    bol : usize, //beginning of line
+    col : usize, //chars since the beginning of the line
     last_token : Token,
     keywords: HashSet<String>, // Store keywords in a HashSet
     operators: HashSet<String>, // Store operators in a HashSet
 }
 
-impl Lexer {
-    pub fn new(content : String) -> Lexer {
-        let content_length = content.len() as usize;
+impl<'a> Lexer<'a> {
+    pub fn new(content : &'a str) -> Lexer<'a> {
         let mut lexer = Lexer {
             content,
-            content_length,
+            chars: content.char_indices().peekable(),
             cursor : 0,
             line: 0,
-            bol : 0,
+            col : 0,
             last_token : Token::default(),
             keywords: HashSet::new(),
             operators: HashSet::new()
         };
 
         let keywords = ["if", "else", "while", "let", "define"];
-        let operators = ["or", "and"];
+        let operators = ["or", "and", "not"];
 
         lexer.keywords.extend(keywords.iter().map(|s| s.to_string()));
         lexer.operators.extend(operators.iter().map(|s| s.to_string()));
@@ -35,84 +38,98 @@ impl Lexer {
         lexer
     }
 
-    fn not_end(&mut self) -> bool{
-        return self.cursor < self.content_length;
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
     }
 
-    fn is_whitespace_without_newline(&mut self, c: char) -> bool {
-        c.is_whitespace() && c != '\n'
+    // Helper method to advance cursor by one char, keeps line and column in sync
+    fn bump(&mut self) -> Option<char> {
+        let (offset, c) = self.chars.next()?;
+        self.cursor = offset + c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 0;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.not_end() && self.is_whitespace_without_newline(self.content.chars().nth(self.cursor as usize).unwrap()) {
-            self.cursor += 1;
+    // Advances while the predicate holds for the next char
+    fn bump_while(&mut self, predicate: impl Fn(&Self, char) -> bool) {
+        while let Some(c) = self.peek() {
+            if !predicate(self, c) {
+                break;
+            }
+            self.bump();
         }
     }
-    // Helper method to advance cursor
-    fn advance_cursor(&mut self, length: usize) {
-        self.cursor += length as usize;
+
+    fn position(&self) -> Position {
+        Position { row: self.line, col: self.col }
+    }
+
+    fn is_whitespace_without_newline(&self, c: char) -> bool {
+        c.is_whitespace() && c != '\n'
     }
 
-    fn get_current_char(&mut self) -> char{
-        let pos = self.cursor;
-        return self.content.chars().nth(pos).unwrap();
+    fn skip_whitespace(&mut self) {
+        self.bump_while(|lexer, c| lexer.is_whitespace_without_newline(c));
     }
 
-    pub fn create_token(&self, kind: TokenKind, position: usize, length: usize) -> Token {
-        let text = self.content[position..(position + length)].to_string();
-        let token = Token { kind: kind, text: text, length: length, position: Position {col : position - self.bol, row : self.line} };
-        return token
+    // start and end are byte offsets into the content, position is where start lies
+    pub fn create_token(&self, kind: TokenKind, start: usize, end: usize, position: Position) -> Token {
+        let text = self.content[start..end].to_string();
+        let length = text.chars().count();
+        let end_position = text.chars().fold(position, |at, c| match c {
+            '\n' => Position { row: at.row + 1, col: 0 },
+            _ => Position { row: at.row, col: at.col + 1 },
+        });
+        Token { kind, text, length, position, end: end_position, span: Span { start, end } }
     }
 
-    fn match_operator(&mut self, start: usize, op: char) -> Token {
-        self.advance_cursor(1);
-        let current_char = self.get_current_char();
+    fn create_error(&self, kind: LexErrorKind, start: usize, end: usize, position: Position) -> LexError {
+        let length = self.content[start..end].chars().count();
+        LexError { kind, length, position, span: Span { start, end } }
+    }
+
+    fn match_operator(&mut self, start: usize, position: Position, op: char) -> Token {
+        self.bump();
     
-        let token = if self.not_end() && current_char == '=' {
-            self.advance_cursor(1);
-            self.create_token(TokenKind::Operator, start, 2)
-        } else if op == '+' || op == '-' {
-            if self.not_end() && current_char == op {
-                self.advance_cursor(1);
-                self.create_token(TokenKind::Operator, start, 2)
-            } else {
-                self.create_token(TokenKind::Operator, start, 1)
-            }
-        } else {
-            self.create_token(TokenKind::Operator, start, 1)
-        };
+        let next = self.peek();
+        if next == Some('=') || ((op == '+' || op == '-') && next == Some(op)) {
+            self.bump();
+        }
     
+        let token = self.create_token(TokenKind::Operator, start, self.cursor, position);
         self.last_token = token.clone();
         token
     }
 
     pub fn is_symbol_start(&self, x : char) -> bool {
-        return x.is_alphabetic() || x == '_';
+        x.is_xid_start() || x == '_'
     }
 
     fn is_symbol(&self, x : char) -> bool {
-        return x.is_ascii_alphanumeric() || x == '_';
+        x.is_xid_continue()
     }
 
     // Helper method to handle symbol token
-    fn handle_symbol(&mut self,) -> Token {
-        let start = self.cursor;
+    fn handle_symbol(&mut self, start: usize, position: Position) -> Token {
+        self.bump(); // the start char was already checked by is_symbol_start
+        self.bump_while(|lexer, c| lexer.is_symbol(c));
     
-        while self.not_end() && self.is_symbol(self.content.chars().nth(self.cursor).unwrap()) {
-            self.advance_cursor(1);
-        }
-        let length = self.cursor - start;
-    
-        let text = self.content[start..(start + length as usize)].to_string();
+        let text = &self.content[start..self.cursor];
         
-        let token = if self.operators.contains(&text) {
-            self.create_token(TokenKind::Operator, start, self.cursor - start)
-        } else if self.keywords.contains(&text) {
-            self.create_token(TokenKind::Keyword, start, length)
+        let kind = if self.operators.contains(text) {
+            TokenKind::Operator
+        } else if self.keywords.contains(text) {
+            TokenKind::Keyword
         } else {
-            self.create_token(TokenKind::Symbol, start, self.cursor - start)
+            TokenKind::Symbol
         };
         
+        let token = self.create_token(kind, start, self.cursor, position);
         self.last_token = token.clone();
         token
     }
@@ -130,189 +147,292 @@ impl Lexer {
     }
 
     // Helper method to handle number token
-    fn handle_number(&mut self) -> Token {
-        let start = self.cursor;
+    // A malformed number is consumed up to its last digit or separator so lexing resumes after it
+    fn handle_number(&mut self, start: usize, position: Position) -> Result<Token, LexError> {
         let mut has_decimal_point = false;
         let mut has_seperator = false;
+        let mut error = None;
     
-        while self.not_end() {
-            let current_char = self.get_current_char();
-    
+        while let Some(current_char) = self.peek() {
             if self.is_number(current_char) {
-                self.advance_cursor(1)
+                self.bump();
             } else if self.is_decimal_point(current_char){
-                if has_decimal_point {
-                    let error_message = format!("Invalid number: multiple decimals in number at row {}, column {}", self.line + 1, self.cursor - self.bol);
-                    panic!("{}", error_message);
+                if has_decimal_point && error.is_none() {
+                    error = Some(LexErrorKind::MultipleDecimalPoints);
                 }
                 has_decimal_point = true;
-                self.advance_cursor(1);
+                self.bump();
             } else if self.is_number_seperator(current_char) {
-                if has_seperator {
-                    let error_message = format!("Invalid number: multiple separators in number at row {}, column {}", self.line + 1, self.cursor - self.bol);
-                    panic!("{}", error_message);
+                if has_seperator && error.is_none() {
+                    error = Some(LexErrorKind::MultipleSeparators);
                 }
                 has_seperator = true;
-                self.advance_cursor(1);
+                self.bump();
             } else {
                 break;
             }
         }
+
+        if let Some(kind) = error {
+            return Err(self.create_error(kind, start, self.cursor, position));
+        }
     
-        let token = self.create_token(TokenKind::Number, start, self.cursor - start);
+        let token = self.create_token(TokenKind::Number, start, self.cursor, position);
         self.last_token = token.clone();
-        token
+        Ok(token)
     }
-    fn handle_string_literal(&mut self) -> Token {
-        let start = self.cursor;
-        self.advance_cursor(1); // Skip opening double quote
-        let mut escaped = false;
+
+    // A bad escape is reported once the closing quote is found, so the rest of the string is skipped
+    fn handle_string_literal(&mut self, start: usize, position: Position) -> Result<Token, LexError> {
+        self.bump(); // Skip opening double quote
+        let content_position = self.position();
+        let mut escape = None;
         let mut string_content = String::new();
+        let mut error = None;
         
-        while self.not_end() {
-            let current_char = self.get_current_char();
-            self.advance_cursor(1);
-            
-            if escaped {
+        loop {
+            let escape_start = (self.cursor, self.position());
+            let current_char = match self.bump() {
+                Some(c) => c,
+                None => break,
+            };
+
+            if let Some((escape_offset, escape_position)) = escape.take() {
                 match current_char {
                     '"' => string_content.push('"'), 
                     '\\' => string_content.push('\\'),
                     _ => {
-                        panic!("Invalid escape sequence: \\{}", current_char);
+                        if error.is_none() {
+                            error = Some(self.create_error(LexErrorKind::InvalidEscape(current_char), escape_offset, self.cursor, escape_position));
+                        }
                     }
                 }
-                escaped = false; 
             } else if current_char == '\\' {
-                escaped = true; 
-            } else if current_char == '"' && !escaped {
-                let mut token = self.create_token(TokenKind::StringLiteral, start + 1, self.cursor - start - 2);
+                escape = Some(escape_start);
+            } else if current_char == '"' {
+                if let Some(error) = error {
+                    return Err(error);
+                }
+                let mut token = self.create_token(TokenKind::StringLiteral, start + 1, self.cursor - 1, content_position);
                 token.text = string_content;
-                return token
+                self.last_token = token.clone();
+                return Ok(token)
             } else {
                 string_content.push(current_char);
             }
         }
-        panic!("Unterminated string literal starting at position {}", start);
+        Err(self.create_error(LexErrorKind::UnterminatedString, start, self.cursor, position))
     }
     
-    pub fn next(&mut self) -> Token {
+    pub fn next(&mut self) -> Result<Token, LexError> {
         //note doesn't skip newline
         self.skip_whitespace();
         //remember a start for multi char tokens
         let start = self.cursor;
+        let position = self.position();
         //return EOF at end of input
-        if self.cursor >= self.content_length {
-            return self.create_token(TokenKind::EOF, start, 0)
-        }
-
-        let current_char = self.get_current_char();
+        let current_char = match self.peek() {
+            Some(c) => c,
+            None => return Ok(self.create_token(TokenKind::EOF, start, start, position)),
+        };
 
-        match current_char {
+        let kind = match current_char {
             '#' => {
-                while self.not_end() && current_char != '\n' {
-                    self.advance_cursor(1);
-                }
-                let token = self.create_token(TokenKind::Comment, start, self.cursor - start);
-                self.last_token = token.clone();
-                return token;
+                self.bump_while(|_, c| c != '\n');
+                TokenKind::Comment
             }
-            '(' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::OpenParen, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            ')' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::CloseParen, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            '{' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::OpenParenCurly, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            '}' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::CloseParenCurly, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            ';' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::LineBreak, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            ':' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::Colon, start, 1);
-                self.last_token = token.clone();
-                token
-            },
-            ',' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::Comma, start, 1);
-                self.last_token = token.clone();
-                token
-            },
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '{' => TokenKind::OpenParenCurly,
+            '}' => TokenKind::CloseParenCurly,
+            ';' => TokenKind::LineBreak,
+            ':' => TokenKind::Colon,
+            ',' => TokenKind::Comma,
+            '^' => TokenKind::Operator,
             '\n' => {
-                self.cursor += 1;
-                let token = self.create_token(TokenKind::NewLine, start, 1);
-                self.line += 1;
-                self.bol = self.cursor;
-                token
+                self.bump();
+                return Ok(self.create_token(TokenKind::NewLine, start, self.cursor, position));
             },
 
             '+' | '-' | '*' | '/' | '=' | '<' | '>' => {
-                self.match_operator(start, current_char)
+                return Ok(self.match_operator(start, position, current_char));
             }
-            '"' => self.handle_string_literal(),
+            '"' => return self.handle_string_literal(start, position),
             '.' => {
-                self.advance_cursor(1);
-                let token = if self.not_end() && self.get_current_char() == '.' && self.last_token.kind == TokenKind::OpenParenCurly {
-                    self.advance_cursor(1);
-                    if self.not_end() && self.get_current_char() == '.' {
-                        self.advance_cursor(1);
-                        self.create_token(TokenKind::Ellipsis, start, 3)
-                    } else {
-                        let error_msg = format!("expected '...' istead of '..' at row {}, column {}", self.line + 1, start - self.bol + 1);
-
-                        panic!("{}", error_msg);
-                    }
+                // consume the whole run of dots (at most three) before deciding what went wrong
+                let after_curly = self.last_token.kind == TokenKind::OpenParenCurly;
+                while self.peek() == Some('.') && self.cursor - start < 3 {
+                    self.bump();
+                }
+                let length = self.cursor - start;
+                if length == 3 && after_curly {
+                    TokenKind::Ellipsis
+                } else if length == 2 && after_curly {
+                    return Err(self.create_error(LexErrorKind::IncompleteEllipsis, start, self.cursor, position));
                 } else {
-                    let error_msg = format!("expected '...' to be in an empty function body at row {}, column {}", self.line + 1, start - self.bol + 1);
-                    panic!("{}", error_msg);
-                };
-                self.last_token = token.clone();
-                token
+                    return Err(self.create_error(LexErrorKind::MisplacedEllipsis, start, self.cursor, position));
+                }
             }
             _ => {
                 if self.is_symbol_start(current_char) {
-                    self.handle_symbol()
+                    return Ok(self.handle_symbol(start, position));
                 } else if self.is_number(current_char) {
-                    self.handle_number()
+                    return self.handle_number(start, position);
                 } else {
-                    self.advance_cursor(1);
-                    self.create_token(TokenKind::Invalid, start, 1)
+                    TokenKind::Invalid
                 }
             }
+        };
+
+        // single char tokens haven't consumed their char yet
+        if self.cursor == start {
+            self.bump();
         }
+        let token = self.create_token(kind, start, self.cursor, position);
+        self.last_token = token.clone();
+        Ok(token)
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    // Lexes the whole input, resuming after every error so all of them are reported at once
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Vec<LexError>> {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
 
         loop {
-            let token = self.next();
-            if token.kind == TokenKind::EOF {
-                tokens.push(token);
-                break;
+            match self.next() {
+                Ok(token) => {
+                    let is_eof = token.kind == TokenKind::EOF;
+                    tokens.push(token);
+                    if is_eof {
+                        break;
+                    }
+                }
+                Err(error) => errors.push(error),
             }
-            tokens.push(token);
         }
-        tokens
+
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Vec<Token> {
+        Lexer::new(source).tokenize().expect("source should lex without errors")
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn lexes_every_token_kind() {
+        let source = "define f(a) {...}\nlet x += 1.5e3 ^ -a; # note\nif not x <= 2 and y-- or z++: \"s\", x >= y == 3 / 4 * 5 $";
+        let tokens = lex(source);
+        let kinds: Vec<(TokenKind, &str)> = tokens.iter().map(|token| (token.kind.clone(), token.text.as_str())).collect();
+        use TokenKind::*;
+        assert_eq!(kinds, [
+            (Keyword, "define"), (Symbol, "f"), (OpenParen, "("), (Symbol, "a"), (CloseParen, ")"),
+            (OpenParenCurly, "{"), (Ellipsis, "..."), (CloseParenCurly, "}"), (NewLine, "\n"),
+            (Keyword, "let"), (Symbol, "x"), (Operator, "+="), (Number, "1.5e3"), (Operator, "^"), (Operator, "-"),
+            (Symbol, "a"), (LineBreak, ";"), (Comment, "# note"), (NewLine, "\n"),
+            (Keyword, "if"), (Operator, "not"), (Symbol, "x"), (Operator, "<="), (Number, "2"), (Operator, "and"),
+            (Symbol, "y"), (Operator, "--"), (Operator, "or"), (Symbol, "z"), (Operator, "++"), (Colon, ":"),
+            (StringLiteral, "s"), (Comma, ","), (Symbol, "x"), (Operator, ">="), (Symbol, "y"), (Operator, "=="),
+            (Number, "3"), (Operator, "/"), (Number, "4"), (Operator, "*"), (Number, "5"), (Invalid, "$"), (EOF, ""),
+        ]);
+        // spans are byte offsets that slice the text back out, string literals leave their quotes outside
+        for token in tokens.iter().filter(|token| token.kind != StringLiteral) {
+            assert_eq!(&source[token.span.start..token.span.end], token.text);
+        }
+        assert_eq!(tokens[9].position, Position { row: 1, col: 0 });
+        assert_eq!(tokens.last().unwrap().span, Span { start: source.len(), end: source.len() });
+    }
+
+    #[test]
+    fn greek_identifiers_are_single_symbols() {
+        let tokens = lex("let αβγ = δ1;");
+        assert_eq!(tokens[1].kind, TokenKind::Symbol);
+        assert_eq!(tokens[1].text, "αβγ");
+        assert_eq!(tokens[1].length, 3);
+        assert_eq!(tokens[1].span, Span { start: 4, end: 10 });
+        assert_eq!(tokens[3].text, "δ1");
+        assert_eq!(tokens[3].position, Position { row: 0, col: 10 });
+        assert_eq!(tokens[3].span, Span { start: 13, end: 16 });
+    }
+
+    #[test]
+    fn columns_count_chars_after_multibyte_strings() {
+        let tokens = lex("let s = \"␣x␣\"; let t = 1;");
+        assert_eq!(tokens[3].kind, TokenKind::StringLiteral);
+        assert_eq!(tokens[3].text, "␣x␣");
+        assert_eq!(tokens[3].length, 3);
+        assert_eq!(tokens[3].position.col, 9);
+        let t = &tokens[6];
+        assert_eq!(t.text, "t");
+        assert_eq!(t.position, Position { row: 0, col: 19 });
+        assert_eq!(&"let s = \"␣x␣\"; let t = 1;"[t.span.start..t.span.end], "t");
+    }
+
+    #[test]
+    fn non_identifier_symbols_are_one_invalid_char() {
+        let tokens = lex("␣ a");
+        assert_eq!(tokens[0].kind, TokenKind::Invalid);
+        assert_eq!(tokens[0].text, "␣");
+        assert_eq!(tokens[0].span, Span { start: 0, end: 3 });
+        assert_eq!(tokens[1].position.col, 2);
+    }
+
+    #[test]
+    fn rows_advance_inside_multiline_strings() {
+        let tokens = lex("\"ä\nö\" x");
+        assert_eq!(tokens[1].text, "x");
+        assert_eq!(tokens[1].position, Position { row: 1, col: 3 });
+    }
+
+    // Every token and error next produces up to the end of the input
+    fn lex_all(source: &str) -> (Vec<Token>, Vec<LexError>) {
+        let mut lexer = Lexer::new(source);
+        let (mut tokens, mut errors) = (Vec::new(), Vec::new());
+        loop {
+            match lexer.next() {
+                Ok(token) if token.kind == TokenKind::EOF => return (tokens, errors),
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    #[test]
+    fn one_run_reports_every_error_and_keeps_lexing() {
+        let source = "let a = \"x\\qy\"; let b = 1.2.3; f({..}); let c = 2; \"open";
+        let (tokens, errors) = lex_all(source);
+        let kinds: Vec<LexErrorKind> = errors.iter().map(|error| error.kind.clone()).collect();
+        assert_eq!(kinds, [
+            LexErrorKind::InvalidEscape('q'),
+            LexErrorKind::MultipleDecimalPoints,
+            LexErrorKind::IncompleteEllipsis,
+            LexErrorKind::UnterminatedString,
+        ]);
+        assert_eq!(errors[0].position, Position { row: 0, col: 10 });
+        assert_eq!(errors[1].position, Position { row: 0, col: 24 });
+        assert_eq!(errors[2].position, Position { row: 0, col: 34 });
+        assert_eq!(errors[3].position, Position { row: 0, col: 51 });
+
+        // the tokens after each error are still there
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, ["let", "a", "=", ";", "let", "b", "=", ";", "f", "(", "{", "}", ")", ";", "let", "c", "=", "2", ";"]);
+        assert_eq!(Lexer::new(source).tokenize().unwrap_err().len(), 4);
+    }
+
+    #[test]
+    fn errors_carry_char_positions() {
+        let errors = Lexer::new("λ = 1.2.3").tokenize().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::MultipleDecimalPoints);
+        assert_eq!(errors[0].position, Position { row: 0, col: 4 });
+        assert_eq!(errors[0].span, Span { start: 5, end: 10 });
+    }
+}
@@ -1,5 +1,8 @@
+use crate::diagnostics::Diagnostic;
 use serde::Serialize;
+use std::fmt;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Default, Clone, Serialize)]
 pub enum TokenKind {
     Number,
@@ -22,18 +25,107 @@ pub enum TokenKind {
     EOF
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
+// row and col are zero based, col counts chars rather than bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
 pub struct Position {
     pub row : usize,
     pub col : usize
 }
 
+// byte offsets into the source, end is exclusive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize
+}
+
+// Where a piece of source starts and ends, end is exclusive like the byte span
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Location {
+    pub start : Position,
+    pub end : Position,
+    pub span : Span
+}
+
+impl Location {
+    // Covers both locations and everything in between
+    pub fn join(&self, other: &Location) -> Location {
+        Location {
+            start: self.start,
+            end: other.end,
+            span: Span { start: self.span.start, end: other.span.end }
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize)]
 pub struct Token {
     pub kind : TokenKind,
     pub text : String,
-    pub length : usize,
-    pub position : Position
+    pub length : usize, //in chars
+    pub position : Position,
+    pub end : Position, //exclusive, on a later row for a string spanning lines
+    pub span : Span
+}
+
+impl Token {
+    pub fn location(&self) -> Location {
+        Location { start: self.position, end: self.end, span: self.span }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum LexErrorKind {
+    MultipleDecimalPoints,
+    MultipleSeparators,
+    InvalidEscape(char),
+    UnterminatedString,
+    IncompleteEllipsis,
+    MisplacedEllipsis,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LexError {
+    pub kind : LexErrorKind,
+    pub length : usize, //in chars
+    pub position : Position,
+    pub span : Span
 }
 
+impl LexError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            LexErrorKind::MultipleDecimalPoints => "Invalid number: multiple decimals in number".to_string(),
+            LexErrorKind::MultipleSeparators => "Invalid number: multiple separators in number".to_string(),
+            LexErrorKind::InvalidEscape(c) => format!("Invalid escape sequence: \\{}", c),
+            LexErrorKind::UnterminatedString => "Unterminated string literal".to_string(),
+            LexErrorKind::IncompleteEllipsis => "expected '...' instead of '..'".to_string(),
+            LexErrorKind::MisplacedEllipsis => "expected '...' to be in an empty function body".to_string(),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(&self.message(), self.span);
+        match &self.kind {
+            LexErrorKind::MultipleDecimalPoints => diagnostic.with_primary_label("second decimal point in this number"),
+            LexErrorKind::MultipleSeparators => diagnostic.with_primary_label("second exponent in this number"),
+            LexErrorKind::InvalidEscape(_) => diagnostic
+                .with_primary_label("unknown escape")
+                .with_help("only \\\" and \\\\ can be escaped"),
+            LexErrorKind::UnterminatedString => {
+                // pointing at the whole rest of the file helps nobody, the opening quote is enough
+                let quote = Span { start: self.span.start, end: self.span.start + 1 };
+                Diagnostic::error(&self.message(), quote).with_primary_label("string starts here but is never closed")
+            }
+            LexErrorKind::IncompleteEllipsis | LexErrorKind::MisplacedEllipsis => diagnostic
+                .with_primary_label("unexpected dots")
+                .with_help("an empty function body is written as `{...}`"),
+        }
+    }
+}
 
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at row {}, column {}", self.message(), self.position.row + 1, self.position.col + 1)
+    }
+}
@@ -0,0 +1,574 @@
+
+use crate::ir_types::*;
+use crate::parser_types::*;
+
+use std::collections::HashMap;
+
+// Lowers a program to the IR.
+//
+// Every variable, literal and intermediate value gets its own region of the tape. Values are natural numbers,
+// subtraction stops at 0 and the boolean operators give 0 or 1. Everything is built out of the primitive
+// instructions, so `a + b` becomes a loop that moves b into a one at a time.
+pub struct Lowering {
+    encodings : Encodings,
+    layout : Layout,
+    blocks : Vec<BasicBlock>,
+    current : BlockId,
+    head : Option<RegionId>, //where the head is known to be inside the current block
+    variables : HashMap<String, RegionId>,
+    constants : HashMap<usize, RegionId>,
+    free : Vec<RegionId>, //temporaries that can be reused
+    errors : Vec<LoweringError>,
+}
+
+impl Lowering {
+    pub fn new(encodings: &Encodings) -> Lowering {
+        let mut lowering = Lowering {
+            encodings: encodings.clone(),
+            layout: Layout::default(),
+            blocks: Vec::new(),
+            current: BlockId(0),
+            head: None,
+            variables: HashMap::new(),
+            constants: HashMap::new(),
+            free: Vec::new(),
+            errors: Vec::new(),
+        };
+        lowering.block();
+        lowering
+    }
+
+    pub fn lower(mut self, program: &Program) -> Result<IrProgram, Vec<LoweringError>> {
+        self.statements(&program.program);
+        self.terminate(Terminator::Halt);
+
+        if self.errors.is_empty() {
+            Ok(IrProgram { layout: self.layout, blocks: self.blocks })
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn error(&mut self, message: &str, label: &str, node: &Node) {
+        self.errors.push(LoweringError::new(message, label, node.location()));
+    }
+
+    // Regions
+
+    fn temporary(&mut self) -> RegionId {
+        self.temporary_in(self.encodings.default)
+    }
+
+    fn temporary_in(&mut self, encoding: Encoding) -> RegionId {
+        match self.free.iter().rposition(|&region| self.layout.get(region).encoding == encoding) {
+            Some(index) => self.free.remove(index),
+            None => {
+                let name = self.layout.regions.len().to_string();
+                self.layout.push(&name, RegionKind::Temporary, encoding)
+            }
+        }
+    }
+
+    fn release(&mut self, region: RegionId) {
+        self.free.push(region);
+    }
+
+    fn constant(&mut self, value: usize) -> RegionId {
+        if let Some(&region) = self.constants.get(&value) {
+            return region;
+        }
+        let region = self.layout.push(&value.to_string(), RegionKind::Constant(value), self.encodings.default);
+        self.constants.insert(value, region);
+        region
+    }
+
+    // Blocks
+
+    fn block(&mut self) -> BlockId {
+        let id = BlockId(self.blocks.len());
+        self.blocks.push(BasicBlock { id, instructions: Vec::new(), terminator: Terminator::Halt });
+        id
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+        self.head = None;
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.blocks[self.current.0].terminator = terminator;
+    }
+
+    fn jump(&mut self, target: BlockId) {
+        self.terminate(Terminator::Jump(target));
+    }
+
+    // Ends the current block in a jump to a new one, for code that is jumped back to
+    fn loop_header(&mut self) -> BlockId {
+        let header = self.block();
+        self.jump(header);
+        self.switch_to(header);
+        header
+    }
+
+    fn branch_zero(&mut self, region: RegionId, zero: BlockId, nonzero: BlockId) {
+        self.move_to(region);
+        self.terminate(Terminator::BranchZero { region, zero, nonzero });
+    }
+
+    // Instructions
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.blocks[self.current.0].instructions.push(instruction);
+    }
+
+    fn move_to(&mut self, region: RegionId) {
+        if self.head != Some(region) {
+            self.emit(Instruction::MoveTo(region));
+            self.head = Some(region);
+        }
+    }
+
+    fn inc(&mut self, region: RegionId) {
+        self.move_to(region);
+        self.emit(Instruction::Increment(region));
+    }
+
+    fn dec(&mut self, region: RegionId) {
+        self.move_to(region);
+        self.emit(Instruction::Decrement(region));
+    }
+
+    fn clear(&mut self, region: RegionId) {
+        self.move_to(region);
+        self.emit(Instruction::Clear(region));
+    }
+
+    // Copy only works between regions with the same encoding, otherwise the value is counted over
+    fn copy(&mut self, from: RegionId, to: RegionId) {
+        let encoding = self.layout.get(from).encoding;
+        if from == to {
+            return;
+        }
+        if encoding == self.layout.get(to).encoding {
+            self.move_to(from);
+            self.emit(Instruction::Copy { from, to });
+            return;
+        }
+        let counter = self.temporary_in(encoding);
+        self.copy(from, counter);
+        self.clear(to);
+        self.add_to(counter, to);
+        self.release(counter);
+    }
+
+    // Arithmetic, `from` and `b` always end up empty
+
+    // to += from
+    fn add_to(&mut self, from: RegionId, to: RegionId) {
+        let header = self.loop_header();
+        let (body, done) = (self.block(), self.block());
+        self.branch_zero(from, done, body);
+        self.switch_to(body);
+        self.dec(from);
+        self.inc(to);
+        self.jump(header);
+        self.switch_to(done);
+    }
+
+    // to -= from
+    fn subtract_from(&mut self, from: RegionId, to: RegionId) {
+        let header = self.loop_header();
+        let (body, done) = (self.block(), self.block());
+        self.branch_zero(from, done, body);
+        self.switch_to(body);
+        self.dec(from);
+        self.dec(to);
+        self.jump(header);
+        self.switch_to(done);
+    }
+
+    // result = a * b by adding a to the result b times
+    fn multiply(&mut self, a: RegionId, b: RegionId, result: RegionId) {
+        let counter = self.temporary();
+        self.clear(result);
+        let header = self.loop_header();
+        let (body, done) = (self.block(), self.block());
+        self.branch_zero(b, done, body);
+        self.switch_to(body);
+        self.dec(b);
+        self.copy(a, counter);
+        self.add_to(counter, result);
+        self.jump(header);
+        self.switch_to(done);
+        self.release(counter);
+    }
+
+    // result = a / b by counting how often b can be taken from a, dividing by 0 gives 0
+    fn divide(&mut self, a: RegionId, b: RegionId, result: RegionId) {
+        let counter = self.temporary();
+        let (round, check, taken, whole, done) = (self.block(), self.block(), self.block(), self.block(), self.block());
+        self.clear(result);
+        self.branch_zero(b, done, round);
+
+        self.switch_to(round);
+        self.copy(b, counter);
+        let inner = self.loop_header();
+        self.branch_zero(counter, whole, check);
+        self.switch_to(check);
+        self.branch_zero(a, done, taken);
+        self.switch_to(taken);
+        self.dec(a);
+        self.dec(counter);
+        self.jump(inner);
+
+        self.switch_to(whole);
+        self.inc(result);
+        self.jump(round);
+        self.switch_to(done);
+        self.release(counter);
+    }
+
+    // result = a ^ b by multiplying 1 by a b times
+    fn power(&mut self, a: RegionId, b: RegionId, result: RegionId) {
+        let previous = self.temporary();
+        let product = self.temporary();
+        self.clear(result);
+        self.inc(result);
+        let header = self.loop_header();
+        let (body, done) = (self.block(), self.block());
+        self.branch_zero(b, done, body);
+        self.switch_to(body);
+        self.dec(b);
+        self.copy(result, previous);
+        self.multiply(a, previous, product);
+        self.copy(product, result);
+        self.jump(header);
+        self.switch_to(done);
+        self.release(product);
+        self.release(previous);
+    }
+
+    // Sets target to 1 when the region is zero (or nonzero when `when_zero` is false), target has to be empty
+    fn set_if(&mut self, region: RegionId, when_zero: bool, target: RegionId) {
+        let (one, done) = (self.block(), self.block());
+        if when_zero {
+            self.branch_zero(region, one, done);
+        } else {
+            self.branch_zero(region, done, one);
+        }
+        self.switch_to(one);
+        self.inc(target);
+        self.jump(done);
+        self.switch_to(done);
+    }
+
+    // Expressions, target is always a temporary so it can't alias a variable the expression reads
+
+    fn expression(&mut self, node: &Node, target: RegionId) {
+        match node {
+            Node::Literal(literal) if literal.kind == LiteralKind::Number => {
+                match literal.raw.parse::<usize>() {
+                    Ok(0) => self.clear(target),
+                    Ok(value) => {
+                        let constant = self.constant(value);
+                        self.copy(constant, target);
+                    }
+                    Err(_) => self.error("only whole numbers can be compiled to a turing machine", "not a whole number", node),
+                }
+            }
+            Node::Literal(_) => self.error("strings can't be compiled to a turing machine", "string used here", node),
+            Node::Identifier(identifier) => match self.variables.get(&identifier.name) {
+                Some(&region) => self.copy(region, target),
+                None => self.error(&format!("cannot find variable '{}'", identifier.name), "not declared before this use", node),
+            },
+            Node::BinaryExpression(binary) => self.binary(binary, target),
+            Node::UnaryExpression(unary) if unary.operator == Operator::Not => {
+                let operand = self.temporary();
+                self.expression(&unary.operand, operand);
+                self.clear(target);
+                self.set_if(operand, true, target);
+                self.release(operand);
+            }
+            Node::UnaryExpression(_) => self.error("negative numbers can't be compiled to a turing machine", "values are natural numbers", node),
+            Node::CallExpression(_) => self.error("function calls can't be compiled to a turing machine yet", "called here", node),
+            _ => self.error("expected an expression", "not an expression", node),
+        }
+    }
+
+    fn binary(&mut self, binary: &BinaryExpression, target: RegionId) {
+        let right = self.temporary();
+
+        match binary.operator {
+            Operator::Add | Operator::Subtract => {
+                self.expression(&binary.left, target);
+                self.expression(&binary.right, right);
+                if binary.operator == Operator::Add {
+                    self.add_to(right, target);
+                } else {
+                    self.subtract_from(right, target);
+                }
+            }
+            Operator::Multiply | Operator::Divide | Operator::Exp => {
+                let left = self.temporary();
+                self.expression(&binary.left, left);
+                self.expression(&binary.right, right);
+                match binary.operator {
+                    Operator::Multiply => self.multiply(left, right, target),
+                    Operator::Divide => self.divide(left, right, target),
+                    _ => self.power(left, right, target),
+                }
+                self.release(left);
+            }
+            // a < b is b - a > 0, a <= b is a - b == 0, the other two swap the sides
+            Operator::Smaller | Operator::Greater | Operator::SmallerEquals | Operator::GreaterEquals => {
+                let (first, second) = match binary.operator {
+                    Operator::Smaller | Operator::GreaterEquals => (&binary.right, &binary.left),
+                    _ => (&binary.left, &binary.right),
+                };
+                let difference = self.temporary();
+                self.expression(first, difference);
+                self.expression(second, right);
+                self.subtract_from(right, difference);
+                self.clear(target);
+                let strict = matches!(binary.operator, Operator::Smaller | Operator::Greater);
+                self.set_if(difference, !strict, target);
+                self.release(difference);
+            }
+            // a == b when both a - b and b - a are 0
+            Operator::Equals => {
+                let left = self.temporary();
+                let forward = self.temporary();
+                self.expression(&binary.left, left);
+                self.expression(&binary.right, right);
+                self.copy(left, forward);
+                self.copy(right, target);
+                self.subtract_from(target, forward);
+                self.subtract_from(left, right);
+                // forward holds a - b and right holds b - a, target was emptied by the subtraction
+                let (half, one, done) = (self.block(), self.block(), self.block());
+                self.branch_zero(forward, half, done);
+                self.switch_to(half);
+                self.branch_zero(right, one, done);
+                self.switch_to(one);
+                self.inc(target);
+                self.jump(done);
+                self.switch_to(done);
+                self.release(forward);
+                self.release(left);
+            }
+            // the right side is only evaluated when needed
+            Operator::And | Operator::Or => {
+                let (second, one, done) = (self.block(), self.block(), self.block());
+                self.clear(target);
+                self.expression(&binary.left, right);
+                if binary.operator == Operator::And {
+                    self.branch_zero(right, done, second);
+                } else {
+                    self.branch_zero(right, second, one);
+                }
+                self.switch_to(second);
+                self.expression(&binary.right, right);
+                self.branch_zero(right, done, one);
+                self.switch_to(one);
+                self.inc(target);
+                self.jump(done);
+                self.switch_to(done);
+            }
+            operator => {
+                let node = Node::BinaryExpression(binary.clone());
+                self.error(&format!("'{}' can't be used inside an expression", operator), "not an expression operator", &node);
+            }
+        }
+        self.release(right);
+    }
+
+    // Statements
+
+    fn statements(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.statement(node);
+        }
+    }
+
+    // Only a literal or a plain variable is written straight into the variable, anything else could read it halfway
+    fn store(&mut self, value: &Node, region: RegionId) {
+        if let Node::Literal(_) | Node::Identifier(_) = value {
+            return self.expression(value, region);
+        }
+        let temporary = self.temporary();
+        self.expression(value, temporary);
+        self.copy(temporary, region);
+        self.release(temporary);
+    }
+
+    fn statement(&mut self, node: &Node) {
+        match node {
+            Node::VariableDeclaration(declaration) => {
+                let name = &declaration.id.name;
+                // a new variable only becomes visible after its initialiser, redeclaring just assigns
+                let region = match self.variables.get(name) {
+                    Some(&region) => region,
+                    None => self.layout.push(name, RegionKind::Variable, self.encodings.of(name)),
+                };
+                self.store(&declaration.init, region);
+                self.variables.insert(name.clone(), region);
+            }
+            Node::Assignment(assignment) => {
+                let region = match self.variables.get(&assignment.id.name) {
+                    Some(&region) => region,
+                    None => {
+                        let message = format!("cannot assign to undeclared variable '{}'", assignment.id.name);
+                        return self.error(&message, "declare it with `let` first", node);
+                    }
+                };
+                let operator = match assignment.operator {
+                    Operator::AddEquals => Operator::Add,
+                    Operator::MinusEquals => Operator::Subtract,
+                    Operator::TimesEquals => Operator::Multiply,
+                    Operator::DivideEquals => Operator::Divide,
+                    other => other,
+                };
+                match (operator, &assignment.value) {
+                    (Operator::Increment, _) => self.inc(region),
+                    (Operator::Decrement, _) => self.dec(region),
+                    (Operator::Assign, Some(value)) => self.store(value, region),
+                    (operator, Some(value)) => {
+                        // `x op= e` is `x = x op e`
+                        let value = Node::BinaryExpression(BinaryExpression {
+                            left: Box::new(Node::Identifier(assignment.id.clone())),
+                            right: value.clone(),
+                            operator,
+                            location: assignment.location,
+                        });
+                        self.store(&value, region);
+                    }
+                    (_, None) => {}
+                }
+            }
+            Node::IfStatement(statement) => {
+                let condition = self.temporary();
+                self.expression(&statement.test, condition);
+                let (consequent, done) = (self.block(), self.block());
+                let alternate = if statement.alternate.is_some() { self.block() } else { done };
+                self.branch_zero(condition, alternate, consequent);
+                self.release(condition);
+
+                self.switch_to(consequent);
+                self.statements(&statement.consequent.body);
+                self.jump(done);
+                if let Some(block) = &statement.alternate {
+                    self.switch_to(alternate);
+                    self.statements(&block.body);
+                    self.jump(done);
+                }
+                self.switch_to(done);
+            }
+            Node::WhileStatement(statement) => {
+                let header = self.loop_header();
+                let condition = self.temporary();
+                self.expression(&statement.test, condition);
+                let (body, done) = (self.block(), self.block());
+                self.branch_zero(condition, done, body);
+                self.release(condition);
+
+                self.switch_to(body);
+                self.statements(&statement.body.body);
+                self.jump(header);
+                self.switch_to(done);
+            }
+            Node::FunctionDeclaration(_) => self.error("functions can't be compiled to a turing machine yet", "declared here", node),
+            Node::Error(_) => {}
+            // an expression on its own has no effect but is still checked
+            expression => {
+                let temporary = self.temporary();
+                self.expression(expression, temporary);
+                self.release(temporary);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lower(source: &str) -> Result<IrProgram, Vec<LoweringError>> {
+        let (program, errors) = Parser::new(Lexer::new(source).tokenize().unwrap()).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        Lowering::new(&Encodings::default()).lower(&program)
+    }
+
+    fn terminators(ir: &IrProgram) -> Vec<Terminator> {
+        ir.blocks.iter().map(|block| block.terminator).collect()
+    }
+
+    #[test]
+    fn lowers_if_else_to_a_branch_that_joins() {
+        let ir = lower("let a = 2;\nif a { a--; } else { a++; }").unwrap();
+        let a = RegionId(0);
+        assert_eq!(terminators(&ir), [
+            Terminator::BranchZero { region: RegionId(2), zero: BlockId(3), nonzero: BlockId(1) },
+            Terminator::Jump(BlockId(2)),
+            Terminator::Halt,
+            Terminator::Jump(BlockId(2)),
+        ]);
+        assert_eq!(ir.blocks[1].instructions, [Instruction::MoveTo(a), Instruction::Decrement(a)]);
+        assert_eq!(ir.blocks[3].instructions, [Instruction::MoveTo(a), Instruction::Increment(a)]);
+        assert_eq!(ir.to_string(), "\
+regions:
+    a   variable    unary
+    #2  constant 2  unary
+    %2  temporary   unary
+bb0:
+    move_to #2
+    copy #2 -> a
+    move_to a
+    copy a -> %2
+    move_to %2
+    if_zero %2 bb3 else bb1
+bb1:
+    move_to a
+    dec a
+    jump bb2
+bb2:
+    halt
+bb3:
+    move_to a
+    inc a
+    jump bb2
+");
+    }
+
+    #[test]
+    fn lowers_while_to_a_loop_with_a_back_edge() {
+        let ir = lower("let a = 2;\nwhile a { a--; }").unwrap();
+        assert_eq!(terminators(&ir), [
+            Terminator::Jump(BlockId(1)),
+            Terminator::BranchZero { region: RegionId(2), zero: BlockId(3), nonzero: BlockId(2) },
+            Terminator::Jump(BlockId(1)),
+            Terminator::Halt,
+        ]);
+        // the test is evaluated again on every round
+        assert_eq!(ir.blocks[1].instructions, [
+            Instruction::MoveTo(RegionId(0)),
+            Instruction::Copy { from: RegionId(0), to: RegionId(2) },
+            Instruction::MoveTo(RegionId(2)),
+        ]);
+        assert!(ir.to_string().ends_with("bb2:\n    move_to a\n    dec a\n    jump bb1\nbb3:\n    halt\n"));
+    }
+
+    #[test]
+    fn reports_functions_and_calls() {
+        let errors = lower("define f(x) {...}\nlet a = f(1);").unwrap_err();
+        let reported: Vec<(&str, &str, usize, usize)> = errors.iter()
+            .map(|error| (error.message.as_str(), error.label.as_str(), error.location.start.row, error.location.start.col))
+            .collect();
+        assert_eq!(reported, [
+            ("functions can't be compiled to a turing machine yet", "declared here", 0, 0),
+            ("function calls can't be compiled to a turing machine yet", "called here", 1, 8),
+        ]);
+    }
+}
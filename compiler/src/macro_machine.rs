@@ -0,0 +1,252 @@
+use crate::dense::{DenseTable, Interner, BLANK_ID};
+use crate::simulator::{Halt, RunReport, Tape};
+use crate::transition_types::*;
+use std::iter;
+
+// count cells of the same symbol in a row
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Run {
+    symbol : u32,
+    count : usize,
+}
+
+// Adds cells next to the head. A side that is empty is blank all the way, so blanks pushed onto it are dropped
+fn push(side: &mut Vec<Run>, symbol: u32, count: usize) {
+    match side.last_mut() {
+        Some(last) if last.symbol == symbol => last.count += count,
+        None if symbol == BLANK_ID => {}
+        _ => side.push(Run { symbol, count }),
+    }
+}
+
+// Removes the count cells next to the head, which all have to be in the nearest run or past the end
+fn take(side: &mut Vec<Run>, count: usize) {
+    if let Some(last) = side.last_mut() {
+        last.count -= count;
+        if last.count == 0 {
+            side.pop();
+        }
+    }
+}
+
+fn pop(side: &mut Vec<Run>) -> u32 {
+    match side.last() {
+        Some(last) => {
+            let symbol = last.symbol;
+            take(side, 1);
+            symbol
+        }
+        None => BLANK_ID,
+    }
+}
+
+// A tape stored as runs of the same symbol on either side of the head, so moving over a run of any length is
+// as cheap as a single step. The nearest run to the head is last on both sides
+#[derive(Debug, Clone)]
+pub struct RunLengthTape {
+    left : Vec<Run>,
+    right : Vec<Run>,
+    symbol : u32, //under the head
+    head : i64,
+}
+
+impl RunLengthTape {
+    // symbols start at cell lowest
+    pub fn new(lowest: i64, symbols: &[u32], head: i64) -> RunLengthTape {
+        let mut tape = RunLengthTape { left: Vec::new(), right: Vec::new(), symbol: BLANK_ID, head };
+        for (index, &symbol) in (lowest..).zip(symbols) {
+            if index < head {
+                push(&mut tape.left, symbol, 1);
+            } else if index == head {
+                tape.symbol = symbol;
+            }
+        }
+        for (offset, &symbol) in symbols.iter().enumerate().rev() {
+            if lowest + offset as i64 > head {
+                push(&mut tape.right, symbol, 1);
+            }
+        }
+        tape
+    }
+
+    pub fn read(&self) -> u32 {
+        self.symbol
+    }
+
+    // How many cells past the head in the direction of moves hold symbol, usize::MAX when it is blank forever
+    fn ahead(&self, moves: i64, symbol: u32) -> usize {
+        let side = if moves > 0 { &self.right } else { &self.left };
+        match side.last() {
+            Some(last) if last.symbol == symbol => last.count,
+            None if symbol == BLANK_ID => usize::MAX,
+            _ => 0,
+        }
+    }
+
+    // Writes symbol in count cells from the head on and moves the head past them. Every cell but the one under
+    // the head has to be in the run ahead of it
+    fn sweep(&mut self, symbol: u32, moves: i64, count: usize) {
+        let (behind, ahead) = match moves {
+            0 => {
+                self.symbol = symbol;
+                return;
+            }
+            1 => (&mut self.left, &mut self.right),
+            _ => (&mut self.right, &mut self.left),
+        };
+        push(behind, symbol, count);
+        take(ahead, count - 1);
+        self.symbol = pop(ahead);
+        self.head += moves * count as i64;
+    }
+
+    pub fn to_tape(&self, symbols: &Interner) -> Tape {
+        let length: usize = self.left.iter().map(|run| run.count).sum();
+        let mut lowest = self.head - length as i64;
+        let mut runs: Vec<Run> = self.left.iter().copied()
+            .chain(iter::once(Run { symbol: self.symbol, count: 1 }))
+            .chain(self.right.iter().rev().copied())
+            .collect();
+        // blanks left behind at either end can span far more cells than are worth spelling out
+        while runs.last().is_some_and(|run| run.symbol == BLANK_ID) {
+            runs.pop();
+        }
+        let leading = runs.iter().take_while(|run| run.symbol == BLANK_ID).count();
+        lowest += runs.drain(..leading).map(|run| run.count as i64).sum::<i64>();
+        let cells = runs.iter().flat_map(|run| iter::repeat_n(symbols.name(run.symbol), run.count));
+        Tape::at(lowest, cells, self.head)
+    }
+}
+
+// Runs a single tape table on a run length encoded tape. A transition that keeps the state and moves over the
+// symbol it reads is taken over the whole run of that symbol in one macro step, which ends in exactly the
+// configuration and step count the plain simulator reaches one step at a time
+pub struct MacroMachine {
+    table : DenseTable,
+    state : u32,
+    tape : RunLengthTape,
+    steps : usize,
+    macro_steps : usize,
+}
+
+impl MacroMachine {
+    // Like simulator::Machine, only the first of several transitions for the same state and symbol is taken
+    pub fn new(table: &TransitionTable, tape: &Tape) -> MacroMachine {
+        let dense = DenseTable::new(table, 1, &tape.symbols());
+        let symbols: Vec<u32> = tape.symbols().iter().map(|symbol| dense.symbols.id(symbol).expect("input symbols are interned")).collect();
+        MacroMachine {
+            state: dense.states.id(&table.start).expect("the start state is interned"),
+            tape: RunLengthTape::new(tape.bounds().map(|(lowest, _)| lowest).unwrap_or(0), &symbols, tape.head()),
+            table: dense,
+            steps: 0,
+            macro_steps: 0,
+        }
+    }
+
+    pub fn macro_steps(&self) -> usize {
+        self.macro_steps
+    }
+
+    fn transition(&self) -> Option<u32> {
+        self.table.lookup(self.state, iter::once(self.tape.read()))
+    }
+
+    pub fn halted(&self) -> Option<Halt> {
+        match self.table.halts(self.state) {
+            Some(halt) => Some(halt),
+            None if self.transition().is_none() => Some(Halt::NoTransition),
+            None => None,
+        }
+    }
+
+    pub fn run(&mut self, max_steps: usize) -> RunReport {
+        while self.steps < max_steps && self.table.halts(self.state).is_none() {
+            let Some(id) = self.transition() else {
+                break;
+            };
+            let (write, moves, next) = (self.table.write[id as usize], self.table.moves[id as usize], self.table.next[id as usize]);
+            let count = if next == self.state && moves != 0 {
+                self.tape.ahead(moves, self.tape.read()).saturating_add(1).min(max_steps - self.steps)
+            } else {
+                1
+            };
+            self.tape.sweep(write, moves, count);
+            self.state = next;
+            self.steps += count;
+            self.macro_steps += 1;
+        }
+
+        RunReport {
+            halt: self.halted(),
+            steps: self.steps,
+            state: self.table.states.name(self.state).to_string(),
+            tapes: vec![self.tape.to_tape(&self.table.symbols)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Machine;
+    use crate::transition_reader::TableReader;
+
+    // Adds a 1 at either end of a block of ones in turn, forever
+    const GROW: &str = "
+        right 1 1 right right
+        right _ 1 left  left
+        left  1 1 left  left
+        left  _ 1 right right
+    ";
+
+    // The 4 state busy beaver, halts after 107 steps
+    const BEAVER: &str = "
+        a _ 1 right b
+        a 1 1 left  b
+        b _ 1 left  a
+        b 1 _ left  c
+        c _ 1 right accept
+        c 1 1 left  d
+        d _ 1 right d
+        d 1 _ right a
+    ";
+
+    type Outcome = (Option<Halt>, usize, String, String, Option<(i64, i64)>, i64);
+
+    fn outcome(report: RunReport) -> Outcome {
+        let tape = &report.tapes[0];
+        (report.halt, report.steps, report.state, tape.to_string(), tape.bounds(), tape.head())
+    }
+
+    fn both(table: &str, input: &str, max_steps: usize) -> (Outcome, Outcome, usize) {
+        let table = TableReader::new(table).read().unwrap();
+        let tape = Tape::from_input(input);
+        let mut accelerated = MacroMachine::new(&table, &tape);
+        let fast = outcome(accelerated.run(max_steps));
+        let plain = outcome(Machine::new(&table, vec![tape]).run(max_steps));
+        (fast, plain, accelerated.macro_steps())
+    }
+
+    #[test]
+    fn ends_where_the_plain_simulator_does() {
+        for (table, input) in [(BEAVER, ""), (GROW, "1"), (GROW, "_1_1"), (GROW, "")] {
+            for max_steps in [0, 1, 7, 107, 1_000, 12_345] {
+                let (fast, plain, _) = both(table, input, max_steps);
+                assert_eq!(fast, plain, "{:?} on '{}' within {} steps", table, input, max_steps);
+            }
+        }
+    }
+
+    #[test]
+    fn sweeps_in_one_macro_step() {
+        let (fast, plain, macro_steps) = both(GROW, "1", 1_000_000);
+        assert_eq!(fast, plain);
+        // every sweep is one macro step plus one to turn around
+        assert!(macro_steps < 3_000, "{} macro steps", macro_steps);
+
+        // running right over blanks never ends, the budget runs out in the middle of the sweep
+        let (fast, plain, macro_steps) = both("right _ _ right right", "", 1_000_000);
+        assert_eq!(fast, plain);
+        assert_eq!(macro_steps, 1);
+    }
+}
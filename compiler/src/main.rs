@@ -1,19 +1,53 @@
+mod checker;
+mod cli;
+mod codegen;
+mod codegen_types;
+mod debugger;
+mod dense;
+mod diagnostics;
+mod driver;
+mod explorer;
+mod formats;
+mod formatter;
+mod history;
+mod ir_types;
+mod jflap;
 mod lexer;
 mod lexer_types;
+mod lowering;
+mod macro_machine;
+mod multitape;
+mod optimizer;
+mod parser;
+mod parser_types;
+mod simulator;
+mod submachines;
+mod trace;
+mod transition_reader;
+mod transition_types;
 
 
-use lexer::Lexer;
+use cli::Options;
 
-use std::fs;
+use std::env;
+use std::process;
 
 
 fn main() { 
-    let code = fs::read_to_string("input.txt").unwrap();
-    let mut lexer = Lexer::new(code.clone());
-
-    let tokens = lexer.tokenize();
-
-    let tokens_json: String = serde_json::to_string_pretty(&tokens).unwrap();
-    fs::write("compiler-steps/step1.json", format!("{}", tokens_json)).unwrap();
-
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(cli::EXIT_USAGE);
+        }
+    };
+
+    match driver::run(&options) {
+        Ok(()) => process::exit(cli::EXIT_SUCCESS),
+        Err(failure) => {
+            failure.report();
+            process::exit(failure.exit_code());
+        }
+    }
 }
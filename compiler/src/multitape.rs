@@ -0,0 +1,286 @@
+use crate::transition_types::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// Turns a k-tape table into a single tape one that keeps the k tapes as tracks of every cell.
+//
+// A cell holds one symbol per track with `|` in between, a track whose head is on the cell has its symbol
+// prefixed by `^`, so `^a|_` has the first head on an `a` and a blank second track. Only the stretch of tape
+// the heads have visited holds such cells, the blank cell on either side of it marks its ends.
+//
+// Every step of the k-tape machine in state q becomes:
+// - q sweeps right from the left end, remembering the symbol under every head (`q:read:a,`)
+// - at the right end the symbols pick the transition, and for every track in turn `q:a,b:find0` sweeps left to
+//   its head, writes the new symbol and moves the head to the neighbouring cell (`mark0`), then `end0` walks
+//   back to the right end
+// - `q:a,b:home` walks back to the left end and enters the next state
+// States without transitions keep their name, so accept and reject states still halt the machine. When q has
+// no transition for the symbols read, the sweep enters q on the blank past the right end, where q has nothing
+// to do either, so the machine halts in q just like the k-tape one.
+//
+// The input is written on the first track before the machine starts, it ends at the first blank and only
+// holds symbols the first tape reads or writes.
+const HEAD: &str = "^";
+const TRACK_SEPARATOR: &str = "|";
+const INIT_STATE: &str = ":init";
+
+// One (symbol, head is here) pair per track
+type Cell = Vec<(String, bool)>;
+
+fn symbol(cell: &Cell) -> String {
+    let tracks: Vec<String> = cell.iter()
+        .map(|(symbol, head)| if *head { format!("{}{}", HEAD, symbol) } else { symbol.clone() })
+        .collect();
+    tracks.join(TRACK_SEPARATOR)
+}
+
+fn blank_cell(tapes: usize) -> Cell {
+    vec![(BLANK.to_string(), false); tapes]
+}
+
+// The symbols every tape can hold
+fn track_alphabets(table: &TransitionTable) -> Vec<BTreeSet<String>> {
+    let mut alphabets = vec![BTreeSet::from([BLANK.to_string()]); table.tapes()];
+    for transition in &table.transitions {
+        for (index, alphabet) in alphabets.iter_mut().enumerate() {
+            alphabet.insert(transition.read[index].clone());
+            alphabet.insert(transition.write[index].clone());
+        }
+    }
+    alphabets
+}
+
+// Every combination of track symbols and heads
+fn cells(alphabets: &[BTreeSet<String>]) -> Vec<Cell> {
+    let mut cells = vec![Vec::new()];
+    for alphabet in alphabets {
+        cells = cells.into_iter()
+            .flat_map(|cell: Cell| alphabet.iter().flat_map(move |symbol| {
+                let cell = cell.clone();
+                [false, true].into_iter().map(move |head| {
+                    let mut extended = cell.clone();
+                    extended.push((symbol.clone(), head));
+                    extended
+                })
+            }))
+            .collect();
+    }
+    cells
+}
+
+pub fn to_single_tape(table: &TransitionTable) -> TransitionTable {
+    let alphabets = track_alphabets(table);
+    let cells = cells(&alphabets);
+    let mut single = TransitionTable { start: INIT_STATE.to_string(), transitions: Vec::new() };
+
+    initialise(&mut single, &alphabets[0], &cells, &table.start);
+
+    // every choice of a nondeterministic table stays a choice at the right end of the read sweep
+    let mut rules: HashMap<&str, HashMap<&[String], Vec<&Transition>>> = HashMap::new();
+    let mut states = Vec::new();
+    for transition in &table.transitions {
+        let choices = rules.entry(&transition.state)
+            .or_insert_with(|| {
+                states.push(transition.state.as_str());
+                HashMap::new()
+            })
+            .entry(&transition.read)
+            .or_default();
+        choices.push(transition);
+        update(&mut single, transition, choices.len(), &cells);
+    }
+    for state in states {
+        read(&mut single, state, &rules[state], &cells);
+    }
+    single
+}
+
+// Writes the input onto the first track with every head on its first cell, then returns to it
+fn initialise(single: &mut TransitionTable, alphabet: &BTreeSet<String>, cells: &[Cell], start: &str) {
+    let tapes = cells[0].len();
+    let scan = format!("{}:scan", INIT_STATE);
+    let home = format!("{}:home", INIT_STATE);
+
+    for input in alphabet {
+        let mut first: Cell = vec![(BLANK.to_string(), true); tapes];
+        first[0].0 = input.clone();
+        single.push(Transition::new(INIT_STATE, input, &symbol(&first), Direction::Right, &scan));
+        if input != BLANK {
+            let mut cell = blank_cell(tapes);
+            cell[0].0 = input.clone();
+            single.push(Transition::new(&scan, input, &symbol(&cell), Direction::Right, &scan));
+        }
+    }
+    single.push(Transition::new(&scan, BLANK, BLANK, Direction::Left, &home));
+    for cell in cells {
+        single.push(Transition::new(&home, &symbol(cell), &symbol(cell), Direction::Left, &home));
+    }
+    single.push(Transition::new(&home, BLANK, BLANK, Direction::Right, start));
+}
+
+// Sweeps right collecting the symbol under every head, only the partial reads that can happen get a state
+fn read(single: &mut TransitionTable, state: &str, rules: &HashMap<&[String], Vec<&Transition>>, cells: &[Cell]) {
+    let name = |seen: &[Option<String>]| -> String {
+        if seen.iter().all(Option::is_none) {
+            state.to_string()
+        } else {
+            let seen: Vec<&str> = seen.iter().map(|symbol| symbol.as_deref().unwrap_or("")).collect();
+            format!("{}:read:{}", state, seen.join(","))
+        }
+    };
+
+    let mut visited = HashSet::new();
+    let mut pending = vec![vec![None; cells[0].len()]];
+    while let Some(seen) = pending.pop() {
+        if !visited.insert(seen.clone()) {
+            continue;
+        }
+        let current = name(&seen);
+        for cell in cells {
+            let mut next = seen.clone();
+            for (track, (symbol, head)) in cell.iter().enumerate() {
+                if *head {
+                    next[track] = Some(symbol.clone());
+                }
+            }
+            single.push(Transition::new(&current, &symbol(cell), &symbol(cell), Direction::Right, &name(&next)));
+            pending.push(next);
+        }
+
+        // every head sits inside the visited stretch, so all of them have been seen at its right end
+        let read: Option<Vec<String>> = seen.iter().cloned().collect();
+        match read.map(|read| rules.get(read.as_slice())) {
+            Some(Some(choices)) => for (index, transition) in choices.iter().enumerate() {
+                single.push(Transition::new(&current, BLANK, BLANK, Direction::Left, &phase(transition, index + 1, "find0")));
+            },
+            Some(None) => single.push(Transition::new(&current, BLANK, BLANK, Direction::Stay, state)),
+            None => {}
+        }
+    }
+}
+
+// Later choices for the same symbols get their number in the name, `q:a,b:2:find0`
+fn phase(transition: &Transition, choice: usize, name: &str) -> String {
+    match choice {
+        1 => format!("{}:{}:{}", transition.state, transition.read.join(","), name),
+        _ => format!("{}:{}:{}:{}", transition.state, transition.read.join(","), choice, name),
+    }
+}
+
+// Carries out one transition track by track, starting and ending each track at the right end
+fn update(single: &mut TransitionTable, transition: &Transition, choice: usize, cells: &[Cell]) {
+    let tapes = transition.tapes();
+    for track in 0..tapes {
+        let find = phase(transition, choice, &format!("find{}", track));
+        let mark = phase(transition, choice, &format!("mark{}", track));
+        let end = phase(transition, choice, &format!("end{}", track));
+        let next = if track + 1 < tapes { phase(transition, choice, &format!("find{}", track + 1)) } else { phase(transition, choice, "home") };
+        let direction = transition.directions[track];
+
+        for cell in cells {
+            if cell[track].1 {
+                let mut written = cell.clone();
+                written[track] = (transition.write[track].clone(), direction == Direction::Stay);
+                let then = if direction == Direction::Stay { &end } else { &mark };
+                single.push(Transition::new(&find, &symbol(cell), &symbol(&written), direction, then));
+            } else {
+                single.push(Transition::new(&find, &symbol(cell), &symbol(cell), Direction::Left, &find));
+            }
+
+            let mut marked = cell.clone();
+            marked[track].1 = true;
+            single.push(Transition::new(&mark, &symbol(cell), &symbol(&marked), Direction::Stay, &end));
+            single.push(Transition::new(&end, &symbol(cell), &symbol(cell), Direction::Right, &end));
+        }
+
+        // a head that leaves the visited stretch grows it by a cell
+        let mut grown = blank_cell(tapes);
+        grown[track].1 = true;
+        single.push(Transition::new(&mark, BLANK, &symbol(&grown), Direction::Stay, &end));
+        single.push(Transition::new(&end, BLANK, BLANK, Direction::Left, &next));
+    }
+
+    let home = phase(transition, choice, "home");
+    for cell in cells {
+        single.push(Transition::new(&home, &symbol(cell), &symbol(cell), Direction::Left, &home));
+    }
+    single.push(Transition::new(&home, BLANK, BLANK, Direction::Right, &transition.next));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Halt, Machine, RunReport, Tape};
+    use crate::transition_reader::TableReader;
+
+    // Accepts a^n b^n by counting the a's on the second tape
+    const BALANCED: &str = "
+        count a,_ a,x right,right count
+        count b,_ b,_ stay,left  match
+        count _,_ _,_ stay,stay  accept
+        match b,x b,_ right,left match
+        match _,_ _,_ stay,stay  accept
+    ";
+
+    // Appends the reverse of its input: w becomes w reverse(w)
+    const MIRROR: &str = "
+        copy   a,_ a,a right,right copy
+        copy   b,_ b,b right,right copy
+        copy   _,_ _,_ stay,left   append
+        append _,a a,a right,left  append
+        append _,b b,b right,left  append
+        append _,_ _,_ stay,stay   accept
+    ";
+
+    fn run(table: &TransitionTable, input: &str) -> RunReport {
+        Machine::new(table, vec![Tape::from_input(input)]).run(100_000)
+    }
+
+    // The first track of a reduced tape
+    fn first_track(tape: &Tape) -> String {
+        let symbols: Vec<String> = tape.symbols().iter()
+            .map(|cell| cell.split(TRACK_SEPARATOR).next().unwrap_or(BLANK).trim_start_matches(HEAD).to_string())
+            .collect();
+        symbols.concat().trim_matches('_').to_string()
+    }
+
+    #[test]
+    fn reads_comma_separated_tuples() {
+        let table = TableReader::new(BALANCED).read().unwrap();
+        assert_eq!(table.tapes(), 2);
+        assert_eq!(table.transitions[1].to_string(), "count b,_ b,_ stay,left match");
+        assert!(TableReader::new("q a,b a right q").read().is_err());
+        assert!(TableReader::new("q a,_ a,b right,up q").read().is_err());
+    }
+
+    #[test]
+    fn every_head_moves_on_its_own() {
+        let table = TableReader::new(MIRROR).read().unwrap();
+        let report = run(&table, "aab");
+        assert_eq!(report.halt, Some(Halt::Accept));
+        assert_eq!(report.tapes[0].to_string(), "aabbaa");
+        assert_eq!(report.tapes[1].to_string(), "aab");
+    }
+
+    #[test]
+    fn single_tape_reduction_halts_the_same_way() {
+        let table = TableReader::new(BALANCED).read().unwrap();
+        let single = to_single_tape(&table);
+        assert_eq!(single.tapes(), 1);
+        // aab, abb and ba get stuck in match
+        for input in ["", "ab", "aabb", "aab", "abb", "ba"] {
+            let (reduced, original) = (run(&single, input), run(&table, input));
+            assert_eq!((reduced.halt, reduced.state), (original.halt, original.state), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn single_tape_reduction_leaves_the_first_tape_on_its_first_track() {
+        let table = TableReader::new(MIRROR).read().unwrap();
+        let single = to_single_tape(&table);
+        for input in ["", "a", "ab", "abba", "bab"] {
+            let report = run(&single, input);
+            assert_eq!(report.halt, Some(Halt::Accept));
+            assert_eq!(first_track(&report.tapes[0]), run(&table, input).tapes[0].to_string());
+        }
+    }
+}
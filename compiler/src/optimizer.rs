@@ -0,0 +1,286 @@
+use crate::simulator::{ACCEPT_STATES, REJECT_STATES};
+use crate::transition_types::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+// Shrinks a table without changing where it halts or what it leaves on the tape, only how many steps it takes.
+// Every pass is safe for nondeterministic and multi-tape tables as well
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Counts {
+    pub states : usize,
+    pub transitions : usize,
+}
+
+impl Counts {
+    pub fn of(table: &TransitionTable) -> Counts {
+        let states: HashSet<&str> = table.transitions.iter()
+            .flat_map(|transition| [transition.state.as_str(), transition.next.as_str()])
+            .chain(std::iter::once(table.start.as_str()))
+            .collect();
+        Counts { states: states.len(), transitions: table.transitions.len() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptimizationReport {
+    pub before : Counts,
+    pub passes : Vec<(String, Counts)>, //the counts after each pass
+}
+
+impl OptimizationReport {
+    pub fn after(&self) -> Counts {
+        self.passes.last().map(|(_, counts)| *counts).unwrap_or(self.before)
+    }
+}
+
+type Pass = fn(&TransitionTable) -> TransitionTable;
+
+// The simulator halts as soon as it enters one of these, whatever transitions they have
+fn named_halt(state: &str) -> bool {
+    ACCEPT_STATES.contains(&state) || REJECT_STATES.contains(&state)
+}
+
+pub fn optimize(table: &TransitionTable) -> (TransitionTable, OptimizationReport) {
+    let mut report = OptimizationReport { before: Counts::of(table), passes: Vec::new() };
+    let passes: [(&str, Pass); 4] = [
+        ("fuse stays", fuse_stays),
+        ("remove unreachable", remove_unreachable),
+        ("merge equivalent", merge_equivalent),
+        ("remove unreachable", remove_unreachable),
+    ];
+
+    let mut table = table.clone();
+    for (name, pass) in passes {
+        table = pass(&table);
+        report.passes.push((name.to_string(), Counts::of(&table)));
+    }
+    (table, report)
+}
+
+// After a transition that doesn't move any head, the symbols the next state reads are the ones just written.
+// When that state has exactly one transition for them both steps become one, so states that only pass the head
+// on (like submachines::goto) drop out of the chain. A state without a transition for them halts the machine
+// and is left alone, so it still halts there, and so is an accept or reject state, whose transitions never run
+fn fuse_stays(table: &TransitionTable) -> TransitionTable {
+    let mut choices: HashMap<(&str, &[String]), Vec<&Transition>> = HashMap::new();
+    for transition in &table.transitions {
+        choices.entry((&transition.state, &transition.read)).or_default().push(transition);
+    }
+
+    let mut fused = TransitionTable { start: table.start.clone(), transitions: Vec::new() };
+    let stays = |transition: &Transition| transition.directions.iter().all(|direction| *direction == Direction::Stay);
+    for transition in &table.transitions {
+        let mut transition = transition.clone();
+        if !stays(&transition) {
+            fused.transitions.push(transition);
+            continue;
+        }
+        // a loop of non-moving transitions never halts, following it once around is enough
+        let mut visited = HashSet::from([transition.state.clone()]);
+        while stays(&transition) && !named_halt(&transition.next) && visited.insert(transition.next.clone()) {
+            let then = match choices.get(&(transition.next.as_str(), transition.write.as_slice())).map(Vec::as_slice) {
+                Some(&[then]) => then,
+                _ => break,
+            };
+            transition.write = then.write.clone();
+            transition.directions = then.directions.clone();
+            transition.next = then.next.clone();
+        }
+        fused.transitions.push(transition);
+    }
+    fused
+}
+
+fn remove_unreachable(table: &TransitionTable) -> TransitionTable {
+    let mut outgoing: HashMap<&str, Vec<&Transition>> = HashMap::new();
+    for transition in &table.transitions {
+        outgoing.entry(&transition.state).or_default().push(transition);
+    }
+
+    let mut reachable = HashSet::from([table.start.as_str()]);
+    let mut pending = vec![table.start.as_str()];
+    while let Some(state) = pending.pop() {
+        for transition in outgoing.get(state).into_iter().flatten() {
+            if reachable.insert(&transition.next) {
+                pending.push(&transition.next);
+            }
+        }
+    }
+
+    let transitions = table.transitions.iter().filter(|transition| reachable.contains(transition.state.as_str())).cloned().collect();
+    TransitionTable { start: table.start.clone(), transitions }
+}
+
+// What a transition reads, writes and how it moves, without its states
+type Action<'a> = (&'a [String], &'a [String], &'a [Direction]);
+
+// Moore style partition refinement: all states with transitions start out in one class and a class is split
+// until its states read, write and move alike and go to the same classes. A state without transitions halts
+// the machine, which its name decides, and an accept or reject state halts it whatever transitions it has, so
+// each of them keeps a class of its own. Every class then becomes its first state, the start state if it is in there
+fn merge_equivalent(table: &TransitionTable) -> TransitionTable {
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    let mut names: Vec<&str> = Vec::new();
+    for state in std::iter::once(table.start.as_str()).chain(table.transitions.iter().flat_map(|transition| [transition.state.as_str(), transition.next.as_str()])) {
+        ids.entry(state).or_insert_with(|| {
+            names.push(state);
+            names.len() - 1
+        });
+    }
+
+    // symbols and directions only need to compare equal, so they are numbered as well
+    let mut actions: HashMap<Action, usize> = HashMap::new();
+    let mut outgoing: Vec<Vec<(usize, usize)>> = vec![Vec::new(); names.len()];
+    for transition in &table.transitions {
+        let count = actions.len();
+        let action = *actions.entry((&transition.read, &transition.write, &transition.directions)).or_insert(count);
+        outgoing[ids[transition.state.as_str()]].push((action, ids[transition.next.as_str()]));
+    }
+
+    let halting = |state: usize| outgoing[state].is_empty() || named_halt(names[state]);
+    let mut classes: Vec<usize> = (0..names.len()).map(|state| if halting(state) { state + 1 } else { 0 }).collect();
+    let mut count = 0;
+    loop {
+        let mut numbering: HashMap<(usize, Vec<(usize, usize)>), usize> = HashMap::new();
+        let refined: Vec<usize> = (0..names.len()).map(|state| {
+            let mut signature: Vec<(usize, usize)> = outgoing[state].iter().map(|&(action, next)| (action, classes[next])).collect();
+            signature.sort_unstable();
+            signature.dedup();
+            let size = numbering.len();
+            *numbering.entry((classes[state], signature)).or_insert(size)
+        }).collect();
+        classes = refined;
+        // refining only ever splits classes, so the partition is stable once their number stops growing
+        if numbering.len() == count {
+            break;
+        }
+        count = numbering.len();
+    }
+
+    let mut representatives: HashMap<usize, usize> = HashMap::new();
+    for (state, class) in classes.iter().enumerate() {
+        representatives.entry(*class).or_insert(state);
+    }
+    let representative = |state: &str| names[representatives[&classes[ids[state]]]];
+
+    let mut merged = TransitionTable { start: table.start.clone(), transitions: Vec::new() };
+    let mut kept = HashSet::new();
+    for transition in &table.transitions {
+        if representative(&transition.state) != transition.state {
+            continue;
+        }
+        let mut transition = transition.clone();
+        transition.next = representative(&transition.next).to_string();
+        // choices of a nondeterministic state can become identical
+        if kept.insert((transition.read.clone(), transition.write.clone(), transition.directions.clone(), transition.state.clone(), transition.next.clone())) {
+            merged.transitions.push(transition);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen;
+    use crate::ir_types::Encodings;
+    use crate::lexer::Lexer;
+    use crate::lowering::Lowering;
+    use crate::parser::Parser;
+    use crate::simulator::{Machine, Tape};
+    use crate::transition_reader::TableReader;
+
+    fn table(text: &str) -> TransitionTable {
+        TableReader::new(text).read().unwrap()
+    }
+
+    #[test]
+    fn removes_unreachable_states() {
+        let optimized = remove_unreachable(&table("
+            start 1 1 right start
+            start _ _ stay  accept
+            lost  1 1 right start
+        "));
+        assert_eq!(optimized.to_string(), "start 1 1 right start\nstart _ _ stay accept\n");
+    }
+
+    #[test]
+    fn merges_states_that_behave_alike() {
+        let (optimized, report) = optimize(&table("
+            start 1 1 right first
+            first 1 1 right other
+            first _ _ stay  accept
+            other 1 1 right first
+            other _ _ stay  accept
+        "));
+        assert_eq!(optimized.to_string(), "start 1 1 right first\nfirst 1 1 right first\nfirst _ _ stay accept\n");
+        assert_eq!(report.before, Counts { states: 4, transitions: 5 });
+        assert_eq!(report.after(), Counts { states: 3, transitions: 3 });
+    }
+
+    #[test]
+    fn keeps_states_that_halt_differently() {
+        let original = table("
+            start 1 1 right one
+            start 0 0 right zero
+            one   _ _ stay  accept
+            zero  _ _ stay  reject
+        ");
+        assert_eq!(merge_equivalent(&original), original);
+    }
+
+    #[test]
+    fn fuses_transitions_that_stay() {
+        let optimized = fuse_stays(&table("
+            start a b stay  goto
+            goto  b c right accept
+            goto  a a stay  goto
+        "));
+        assert_eq!(optimized.transitions[0].to_string(), "start a c right accept");
+        // a loop of stays is followed once around and left in place
+        assert_eq!(optimized.transitions[2].to_string(), "goto a a stay goto");
+    }
+
+    #[test]
+    fn named_halting_states_are_kept_even_with_transitions() {
+        // accept moves just like wander, but a machine entering it halts right away
+        let original = table("
+            start  1 1 right wander
+            start  0 0 right accept
+            wander _ _ right wander
+            accept _ _ right accept
+        ");
+        assert_eq!(merge_equivalent(&original), original);
+
+        // fusing past reject would carry on to accept
+        let original = table("
+            start  1 1 stay reject
+            reject 1 1 stay accept
+        ");
+        assert_eq!(fuse_stays(&original), original);
+
+        for input in ["0", "1"] {
+            let run = |table: &TransitionTable| Machine::new(table, vec![Tape::from_input(input)]).run(100);
+            let (plain, optimized) = (run(&original), run(&optimize(&original).0));
+            assert_eq!((optimized.halt, optimized.state), (plain.halt, plain.state));
+        }
+    }
+
+    #[test]
+    fn compiled_programs_keep_their_results() {
+        let source = "let a = 3; let b = 0; while a > 0 { a = a - 1; b = b + 2; } let c = b * 2;";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let (program, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty());
+        let ir = Lowering::new(&Encodings::default()).lower(&program).unwrap();
+        let compiled = codegen::generate(&ir);
+        let (optimized, report) = optimize(&compiled);
+        assert!(report.after().transitions < report.before.transitions);
+
+        let run = |table: &TransitionTable| Machine::new(table, vec![Tape::new()]).run(10_000_000);
+        let (plain, fast) = (run(&compiled), run(&optimized));
+        assert_eq!(fast.halt, plain.halt);
+        assert!(fast.steps < plain.steps);
+        assert_eq!(codegen::decode(&ir.layout, &fast.tapes[0]), codegen::decode(&ir.layout, &plain.tapes[0]));
+    }
+}
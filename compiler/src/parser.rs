@@ -0,0 +1,621 @@
+
+use crate::lexer_types::*;
+use crate::parser_types::*;
+
+// Binding powers for the precedence climbing in Parser::handle_expression, loosest first
+//
+//   operator              left  right  associativity
+//   or                      1     2    left
+//   and                     3     4    left
+//   not (prefix)            -     5
+//   == < > <= >=            7     8    chained, `a < b < c` means `a < b and b < c`
+//   + -                     9    10    left
+//   * /                    11    12    left
+//   - (prefix)              -    13
+//   ^                      16    15    right, so `-2 ^ 2` is `-(2 ^ 2)` and `2 ^ -1` parses
+//   calls and ( )          tightest
+fn infix_binding_power(op: &Operator) -> Option<(u8, u8)> {
+    match op {
+        Operator::Or => Some((1, 2)),
+        Operator::And => Some((3, 4)),
+        Operator::Equals | Operator::Smaller | Operator::Greater | Operator::SmallerEquals | Operator::GreaterEquals => Some((7, 8)),
+        Operator::Add | Operator::Subtract => Some((9, 10)),
+        Operator::Multiply | Operator::Divide => Some((11, 12)),
+        Operator::Exp => Some((16, 15)),
+        _ => None,
+    }
+}
+
+fn prefix_binding_power(op: &Operator) -> Option<u8> {
+    match op {
+        Operator::Not => Some(5),
+        Operator::Subtract => Some(13),
+        _ => None,
+    }
+}
+
+pub struct Parser {
+    tokens : Vec<Token>,
+    position : usize,
+    errors : Vec<ParseError>,
+}
+
+impl Parser {
+    // Newlines and comments carry no meaning for the grammar so they are dropped up front
+    pub fn new(tokens: Vec<Token>) -> Parser {
+        let mut tokens: Vec<Token> = tokens.into_iter()
+            .filter(|token| token.kind != TokenKind::NewLine && token.kind != TokenKind::Comment)
+            .collect();
+        if tokens.last().map(|token| token.kind != TokenKind::EOF).unwrap_or(true) {
+            let mut eof = Token::default();
+            if let Some(last) = tokens.last() {
+                eof.position = last.end;
+                eof.end = last.end;
+                eof.span = Span { start: last.span.end, end: last.span.end };
+            }
+            tokens.push(eof);
+        }
+
+        Parser {
+            tokens,
+            position : 0,
+            errors : Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        let index = (self.position + n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    // Returns the current token and moves on, EOF is never passed
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::EOF {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn check(&self, kind: TokenKind, text: Option<&str>) -> bool {
+        let token = self.peek();
+        token.kind == kind && text.map(|text| token.text == text).unwrap_or(true)
+    }
+
+    fn validate_token(&mut self, kind: TokenKind, text: Option<&str>, expected: &str) -> Result<Token, ParseError> {
+        if self.check(kind, text) {
+            Ok(self.advance())
+        } else {
+            Err(ParseError::expected(expected, self.peek()))
+        }
+    }
+
+    // From the start token up to and including the last consumed token
+    fn location_from(&self, start: &Token) -> Location {
+        let last = &self.tokens[self.position.saturating_sub(1)];
+        if last.span.end < start.span.end {
+            return start.location();
+        }
+        start.location().join(&last.location())
+    }
+
+    fn validate_closer(&mut self, kind: TokenKind, delimiter: &str, open: &Token) -> Result<Token, ParseError> {
+        if self.check(kind, None) {
+            Ok(self.advance())
+        } else {
+            Err(ParseError::unclosed(delimiter, open, self.peek()))
+        }
+    }
+
+    fn peek_operator(&self) -> Option<Operator> {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::Operator => Operator::from_text(&token.text),
+            _ => None,
+        }
+    }
+
+    fn handle_expression(&mut self) -> Result<Node, ParseError> {
+        self.handle_expression_bp(0)
+    }
+
+    // Precedence climbing, only operators binding tighter than min_bp are consumed
+    // stops at the first token that can't continue the expression, e.g. `;` or `{`
+    fn handle_expression_bp(&mut self, min_bp: u8) -> Result<Node, ParseError> {
+        let mut left = self.handle_prefix()?;
+        // right operand of the previous comparison on this level, for chains like `a < b < c`
+        let mut chain: Option<Node> = None;
+
+        while let Some(operator) = self.peek_operator() {
+            let (left_bp, right_bp) = match infix_binding_power(&operator) {
+                Some(bp) => bp,
+                None => break,
+            };
+            if left_bp < min_bp {
+                break;
+            }
+            self.advance();
+            let right = self.handle_expression_bp(right_bp)?;
+
+            let location = left.location().join(&right.location());
+            left = match chain.take() {
+                Some(previous) if operator.is_comparison() => {
+                    let comparison = Node::BinaryExpression(BinaryExpression {
+                        location: previous.location().join(&right.location()),
+                        left: Box::new(previous),
+                        right: Box::new(right.clone()),
+                        operator,
+                    });
+                    Node::BinaryExpression(BinaryExpression { left: Box::new(left), right: Box::new(comparison), operator: Operator::And, location })
+                }
+                _ => Node::BinaryExpression(BinaryExpression { left: Box::new(left), right: Box::new(right.clone()), operator, location }),
+            };
+            if operator.is_comparison() {
+                chain = Some(right);
+            }
+        }
+        Ok(left)
+    }
+
+    // Literals, identifiers, calls, parenthesised expressions and prefix operators
+    fn handle_prefix(&mut self) -> Result<Node, ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number => {
+                self.advance();
+                Ok(Node::Literal(Literal { kind: LiteralKind::Number, location: token.location(), raw: token.text }))
+            }
+            TokenKind::StringLiteral => {
+                self.advance();
+                Ok(Node::Literal(Literal { kind: LiteralKind::String, location: token.location(), raw: token.text }))
+            }
+            TokenKind::Symbol => {
+                self.advance();
+                let id = Identifier { location: token.location(), name: token.text };
+                if self.check(TokenKind::OpenParen, None) {
+                    Ok(Node::CallExpression(self.handle_call(id)?))
+                } else {
+                    Ok(Node::Identifier(id))
+                }
+            }
+            TokenKind::OpenParen => {
+                self.advance();
+                let inner = self.handle_expression()?;
+                self.validate_closer(TokenKind::CloseParen, ")", &token)?;
+                Ok(inner)
+            }
+            TokenKind::Operator => {
+                let operator = Operator::from_text(&token.text);
+                match operator.and_then(|op| prefix_binding_power(&op).map(|bp| (op, bp))) {
+                    Some((operator, right_bp)) => {
+                        self.advance();
+                        let operand = self.handle_expression_bp(right_bp)?;
+                        let location = token.location().join(&operand.location());
+                        Ok(Node::UnaryExpression(UnaryExpression { operand: Box::new(operand), operator, location }))
+                    }
+                    None => Err(ParseError::expected("an operand", &token)),
+                }
+            }
+            _ => Err(ParseError::expected("an operand", &token)),
+        }
+    }
+
+    // <identifier>(<expression>, ...)
+    fn handle_call(&mut self, callee: Identifier) -> Result<CallExpression, ParseError> {
+        let open = self.validate_token(TokenKind::OpenParen, None, "'('")?;
+        let mut arguments = Vec::new();
+        if !self.check(TokenKind::CloseParen, None) {
+            loop {
+                arguments.push(self.handle_expression()?);
+                if !self.check(TokenKind::Comma, None) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        let close = self.validate_closer(TokenKind::CloseParen, ")", &open)?;
+        let location = callee.location.join(&close.location());
+        Ok(CallExpression { callee, arguments, location })
+    }
+
+    fn handle_identifier(&mut self, expected: &str) -> Result<Identifier, ParseError> {
+        let token = self.validate_token(TokenKind::Symbol, None, expected)?;
+        Ok(Identifier { location: token.location(), name: token.text })
+    }
+
+    fn handle_end_of_statement(&mut self) -> Result<(), ParseError> {
+        self.validate_token(TokenKind::LineBreak, None, "';'")?;
+        Ok(())
+    }
+
+    // let <identifier> = <expression>;
+    fn handle_assignment(&mut self) -> Result<VariableDeclaration, ParseError> {
+        let start = self.validate_token(TokenKind::Keyword, Some("let"), "'let'")?;
+        let id = self.handle_identifier("an identifier in assignment statement")?;
+        self.validate_token(TokenKind::Operator, Some("="), "'=' in assignment statement")?;
+        let init = self.handle_expression()?;
+        self.handle_end_of_statement()?;
+
+        Ok(VariableDeclaration { id, init: Box::new(init), location: self.location_from(&start) })
+    }
+
+    // <identifier> = <expression>; with any of = += -= *= /=, or <identifier>++; and <identifier>--;
+    fn handle_reassignment(&mut self) -> Result<Assignment, ParseError> {
+        let start = self.peek().clone();
+        let id = self.handle_identifier("an identifier")?;
+        let operator = match self.peek_operator() {
+            Some(operator) if operator.is_assignment() => operator,
+            _ => return Err(ParseError::expected("an assignment operator", self.peek())),
+        };
+        self.advance();
+
+        let value = match operator {
+            Operator::Increment | Operator::Decrement => None,
+            _ => Some(Box::new(self.handle_expression()?)),
+        };
+        self.handle_end_of_statement()?;
+
+        Ok(Assignment { id, operator, value, location: self.location_from(&start) })
+    }
+
+    // { <statement>* }
+    fn handle_block(&mut self) -> Result<Block, ParseError> {
+        let start = self.validate_token(TokenKind::OpenParenCurly, None, "'{'")?;
+        let mut body = Vec::new();
+        while !self.check(TokenKind::CloseParenCurly, None) {
+            if self.check(TokenKind::EOF, None) {
+                return Err(ParseError::unclosed("}", &start, self.peek()));
+            }
+            if let Some(node) = self.handle_statement_or_recover() {
+                body.push(node);
+            }
+        }
+        self.advance();
+        Ok(Block { body, location: self.location_from(&start) })
+    }
+
+    // if <expression> { ... } [else { ... } | else if ...]
+    fn handle_if(&mut self) -> Result<IfStatement, ParseError> {
+        let start = self.validate_token(TokenKind::Keyword, Some("if"), "'if'")?;
+        let test = self.handle_expression()?;
+        let consequent = self.handle_block()?;
+
+        let mut alternate = None;
+        if self.check(TokenKind::Keyword, Some("else")) {
+            self.advance();
+            if self.check(TokenKind::Keyword, Some("if")) {
+                let else_if = self.handle_if()?;
+                alternate = Some(Block { location: else_if.location, body: vec![Node::IfStatement(else_if)] });
+            } else if self.check(TokenKind::OpenParenCurly, None) {
+                alternate = Some(self.handle_block()?);
+            } else {
+                return Err(ParseError::expected("'{' or 'if' after 'else'", self.peek()));
+            }
+        }
+
+        Ok(IfStatement {
+            test: Box::new(test),
+            consequent,
+            alternate,
+            location: self.location_from(&start),
+        })
+    }
+
+    // while <expression> { ... }
+    fn handle_while(&mut self) -> Result<WhileStatement, ParseError> {
+        let start = self.validate_token(TokenKind::Keyword, Some("while"), "'while'")?;
+        let test = self.handle_expression()?;
+        let body = self.handle_block()?;
+
+        Ok(WhileStatement { test: Box::new(test), body, location: self.location_from(&start) })
+    }
+
+    // define <identifier>(<identifier>, ...) { ... } or with an empty `{...}` body
+    fn handle_function_assignment(&mut self) -> Result<FunctionDeclaration, ParseError> {
+        let start = self.validate_token(TokenKind::Keyword, Some("define"), "'define'")?;
+        let id = self.handle_identifier("a function name")?;
+
+        let open = self.validate_token(TokenKind::OpenParen, None, "'(' to start the parameter list")?;
+        let mut params = Vec::new();
+        if !self.check(TokenKind::CloseParen, None) {
+            loop {
+                params.push(self.handle_identifier("a parameter name")?);
+                if !self.check(TokenKind::Comma, None) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.validate_closer(TokenKind::CloseParen, ")", &open)?;
+
+        let body = if self.check(TokenKind::OpenParenCurly, None) && self.peek_nth(1).kind == TokenKind::Ellipsis {
+            self.advance();
+            self.advance();
+            self.validate_token(TokenKind::CloseParenCurly, None, "'}' after '...'")?;
+            None
+        } else {
+            Some(self.handle_block()?)
+        };
+
+        Ok(FunctionDeclaration { id, params, body, location: self.location_from(&start) })
+    }
+
+    fn handle_keyword(&mut self) -> Result<Node, ParseError> {
+        let token = self.peek().clone();
+        match token.text.as_str() {
+            "let" => Ok(Node::VariableDeclaration(self.handle_assignment()?)),
+            "if" => Ok(Node::IfStatement(self.handle_if()?)),
+            "while" => Ok(Node::WhileStatement(self.handle_while()?)),
+            "define" => Ok(Node::FunctionDeclaration(self.handle_function_assignment()?)),
+            _ => Err(ParseError::expected("a statement", &token)),
+        }
+    }
+
+    // Returns None for an empty statement
+    fn handle_statement(&mut self) -> Result<Option<Node>, ParseError> {
+        match self.peek().kind {
+            TokenKind::LineBreak => {
+                self.advance();
+                Ok(None)
+            },
+            TokenKind::Keyword => self.handle_keyword().map(Some),
+            TokenKind::Symbol if Operator::from_text(&self.peek_nth(1).text).map(|op| op.is_assignment()).unwrap_or(false) => {
+                Ok(Some(Node::Assignment(self.handle_reassignment()?)))
+            },
+            _ => {
+                let node = self.handle_expression()?;
+                self.handle_end_of_statement()?;
+                Ok(Some(node))
+            },
+        }
+    }
+
+    // Panic-mode recovery: skips ahead to the end of the statement and leaves an error node in its place
+    fn handle_statement_or_recover(&mut self) -> Option<Node> {
+        let start_position = self.position;
+        let start = self.peek().clone();
+        match self.handle_statement() {
+            Ok(node) => node,
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize(start_position);
+                Some(Node::Error(ErrorNode { location: self.location_from(&start) }))
+            }
+        }
+    }
+
+    // Skips tokens up to a `;` (consumed), a `}` closing the enclosing block or the start of a new line
+    // braces opened while skipping are skipped as a whole, so a broken `if` header drops its body too
+    fn synchronize(&mut self, start_position: usize) {
+        let mut depth = 0;
+        loop {
+            let token = self.peek().clone();
+            let new_line = self.position > start_position && token.position.row > self.tokens[self.position - 1].position.row;
+            match token.kind {
+                TokenKind::EOF => return,
+                TokenKind::LineBreak if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::CloseParenCurly if depth == 0 => return,
+                TokenKind::CloseParenCurly => {
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                    continue;
+                }
+                TokenKind::OpenParenCurly => depth += 1,
+                _ if depth == 0 && new_line => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    // Always returns a program, statements that failed to parse become error nodes
+    pub fn parse(&mut self) -> (Program, Vec<ParseError>) {
+        let start = self.peek().clone();
+        let mut program = Vec::new();
+
+        while !self.check(TokenKind::EOF, None) {
+            if self.check(TokenKind::CloseParenCurly, None) {
+                let token = self.advance();
+                self.errors.push(ParseError::expected("a statement", &token));
+                program.push(Node::Error(ErrorNode { location: token.location() }));
+                continue;
+            }
+            if let Some(node) = self.handle_statement_or_recover() {
+                program.push(node);
+            }
+        }
+        (Program { program, location: self.location_from(&start) }, std::mem::take(&mut self.errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse(source: &str) -> (Program, Vec<ParseError>) {
+        Parser::new(Lexer::new(source).tokenize().unwrap()).parse()
+    }
+
+    // Writes a node as an s-expression so the shape of a tree fits in one assertion
+    fn shape(node: &Node) -> String {
+        let list = |nodes: &[Node]| nodes.iter().map(shape).collect::<Vec<_>>().join(" ");
+        let block = |block: &Block| format!("{{{}}}", list(&block.body));
+        match node {
+            Node::Literal(literal) if literal.kind == LiteralKind::String => format!("{:?}", literal.raw),
+            Node::Literal(literal) => literal.raw.clone(),
+            Node::Identifier(id) => id.name.clone(),
+            Node::BinaryExpression(binary) => format!("({} {} {})", binary.operator, shape(&binary.left), shape(&binary.right)),
+            Node::UnaryExpression(unary) => format!("({} {})", unary.operator, shape(&unary.operand)),
+            Node::CallExpression(call) => format!("(call {} [{}])", call.callee.name, list(&call.arguments)),
+            Node::VariableDeclaration(declaration) => format!("(let {} {})", declaration.id.name, shape(&declaration.init)),
+            Node::Assignment(assignment) => match &assignment.value {
+                Some(value) => format!("({} {} {})", assignment.operator, assignment.id.name, shape(value)),
+                None => format!("({} {})", assignment.operator, assignment.id.name),
+            },
+            Node::IfStatement(statement) => match &statement.alternate {
+                Some(alternate) => format!("(if {} {} {})", shape(&statement.test), block(&statement.consequent), block(alternate)),
+                None => format!("(if {} {})", shape(&statement.test), block(&statement.consequent)),
+            },
+            Node::WhileStatement(statement) => format!("(while {} {})", shape(&statement.test), block(&statement.body)),
+            Node::FunctionDeclaration(function) => {
+                let params: Vec<&str> = function.params.iter().map(|param| param.name.as_str()).collect();
+                let body = function.body.as_ref().map(block).unwrap_or_else(|| "{...}".to_string());
+                format!("(define {} [{}] {})", function.id.name, params.join(" "), body)
+            }
+            Node::Error(_) => "<error>".to_string(),
+        }
+    }
+
+    // The shape of every statement of a source that has to parse cleanly
+    fn shapes(source: &str) -> Vec<String> {
+        let (program, errors) = parse(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+        program.program.iter().map(shape).collect()
+    }
+
+    // The shape of a single expression statement
+    fn expression(source: &str) -> String {
+        let shapes = shapes(&format!("{};", source));
+        assert_eq!(shapes.len(), 1);
+        shapes[0].clone()
+    }
+
+    #[test]
+    fn follows_the_precedence_table() {
+        assert_eq!(expression("1 + 2 * 3 - 4 / 5"), "(- (+ 1 (* 2 3)) (/ 4 5))");
+        assert_eq!(expression("a or b and c"), "(or a (and b c))");
+        assert_eq!(expression("a + 1 < b * 2 and c"), "(and (< (+ a 1) (* b 2)) c)");
+        assert_eq!(expression("1 - 2 - 3"), "(- (- 1 2) 3)");
+    }
+
+    #[test]
+    fn exponents_are_right_associative_and_bind_tighter_than_prefixes() {
+        assert_eq!(expression("2 ^ 3 ^ 2"), "(^ 2 (^ 3 2))");
+        assert_eq!(expression("-2 ^ 2"), "(- (^ 2 2))");
+        assert_eq!(expression("2 ^ -1"), "(^ 2 (- 1))");
+        assert_eq!(expression("-a * b"), "(* (- a) b)");
+    }
+
+    #[test]
+    fn chains_comparisons() {
+        assert_eq!(expression("a < b < c"), "(and (< a b) (< b c))");
+        assert_eq!(expression("not a < b < c"), "(not (and (< a b) (< b c)))");
+        assert_eq!(expression("a <= b == c > d"), "(and (and (<= a b) (== b c)) (> c d))");
+    }
+
+    #[test]
+    fn parentheses_and_calls_group() {
+        assert_eq!(expression("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(expression("2 ^ (3 ^ 2) ^ 1"), "(^ 2 (^ (^ 3 2) 1))");
+        assert_eq!(expression("-(a - b)"), "(- (- a b))");
+        assert_eq!(expression("f(g(1), 2 + 3)"), "(call f [(call g [1]) (+ 2 3)])");
+        assert_eq!(expression("f() * -h(x)"), "(* (call f []) (- (call h [x])))");
+    }
+
+    #[test]
+    fn parses_declarations_and_assignments() {
+        assert_eq!(shapes("let x = 1; let s = \"hi\"; x += 2; x++; y = x;"), [
+            "(let x 1)", "(let s \"hi\")", "(+= x 2)", "(++ x)", "(= y x)",
+        ]);
+    }
+
+    #[test]
+    fn parses_if_else_chains() {
+        assert_eq!(shapes("if a { x = 1; }"), ["(if a {(= x 1)})"]);
+        assert_eq!(shapes("if a { x = 1; } else { x = 2; }"), ["(if a {(= x 1)} {(= x 2)})"]);
+        assert_eq!(shapes("if a { } else if b { x = 1; } else { x = 2; }"), ["(if a {} {(if b {(= x 1)} {(= x 2)})})"]);
+    }
+
+    #[test]
+    fn parses_loops_and_functions() {
+        assert_eq!(shapes("while x > 0 { x--; }"), ["(while (> x 0) {(-- x)})"]);
+        assert_eq!(shapes("define f(a, b) { let c = a; }"), ["(define f [a b] {(let c a)})"]);
+        assert_eq!(shapes("define g() {...}"), ["(define g [] {...})"]);
+    }
+
+    fn at(row: usize, col: usize) -> Position {
+        Position { row, col }
+    }
+
+    #[test]
+    fn nodes_span_their_source() {
+        let source = "let s = \"ab\ncd\";\nlet x = f(1) +\n  2;";
+        let (program, _) = parse(source);
+        let Node::VariableDeclaration(string) = &program.program[0] else { panic!("{:?}", program.program[0]) };
+        assert_eq!((string.location.start, string.location.end), (at(0, 0), at(1, 4)));
+        // the literal covers the text between the quotes, down to the line it ends on
+        let literal = string.init.location();
+        assert_eq!((literal.start, literal.end), (at(0, 9), at(1, 2)));
+        assert_eq!(&source[literal.span.start..literal.span.end], "ab\ncd");
+
+        let Node::VariableDeclaration(sum) = &program.program[1] else { panic!("{:?}", program.program[1]) };
+        assert_eq!((sum.location.start, sum.location.end), (at(2, 0), at(3, 4)));
+        assert_eq!((sum.id.location.start, sum.id.location.end), (at(2, 4), at(2, 5)));
+        let Node::BinaryExpression(binary) = sum.init.as_ref() else { panic!("{:?}", sum.init) };
+        assert_eq!((binary.location.start, binary.location.end), (at(2, 8), at(3, 3)));
+        assert_eq!((binary.left.location().start, binary.left.location().end), (at(2, 8), at(2, 12)));
+        assert_eq!(&source[binary.location.span.start..binary.location.span.end], "f(1) +\n  2");
+        assert_eq!((program.location.start, program.location.end), (at(0, 0), at(3, 4)));
+    }
+
+    #[test]
+    fn recovers_from_every_broken_statement() {
+        let source = "let a = ;\nlet b = 2 3\nlet c = 4;\n}\nlet d = (1 + 2;\nlet e = 5;";
+        let (program, errors) = parse(source);
+        let reported: Vec<(String, Position, Span)> = errors.iter().map(|error| (error.message(), error.position, error.span)).collect();
+        assert_eq!(reported, [
+            ("expected an operand but found ';'".to_string(), at(0, 8), Span { start: 8, end: 9 }),
+            ("expected ';' but found '3'".to_string(), at(1, 10), Span { start: 20, end: 21 }),
+            ("expected a statement but found '}'".to_string(), at(3, 0), Span { start: 33, end: 34 }),
+            ("expected ')' but found ';'".to_string(), at(4, 14), Span { start: 49, end: 50 }),
+        ]);
+        assert!(matches!(&errors[3].kind, ParseErrorKind::Unclosed { open, .. } if *open == Span { start: 43, end: 44 }));
+
+        let statements: Vec<String> = program.program.iter().map(shape).collect();
+        assert_eq!(statements, ["<error>", "<error>", "(let c 4)", "<error>", "<error>", "(let e 5)"]);
+        // an error node covers the tokens skipped for it
+        let skipped: Vec<&str> = program.program.iter().map(|node| &source[node.location().span.start..node.location().span.end]).collect();
+        assert_eq!(skipped, ["let a = ;", "let b = 2 3", "let c = 4;", "}", "let d = (1 + 2;", "let e = 5;"]);
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let (program, errors) = parse("while x {\n    let a = ;\n    x--;\n}\nf(1;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].position, at(1, 12));
+        assert_eq!(errors[1].position, at(4, 3));
+        let statements: Vec<String> = program.program.iter().map(shape).collect();
+        assert_eq!(statements, ["(while x {<error> (-- x)})", "<error>"]);
+    }
+
+    #[test]
+    fn parses_nested_blocks() {
+        let source = "
+            define f(a) {
+                while a {
+                    if a == 1 {
+                        a--;
+                    } else {
+                        while b { b--; }
+                    }
+                }
+            }
+            f(1);
+        ";
+        assert_eq!(shapes(source), [
+            "(define f [a] {(while a {(if (== a 1) {(-- a)} {(while b {(-- b)})})})})",
+            "(call f [1])",
+        ]);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::diagnostics::Diagnostic;
+use crate::lexer_types::{Location, Position, Span, Token, TokenKind};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Node {
+    FunctionDeclaration(FunctionDeclaration),
+    VariableDeclaration(VariableDeclaration),
+    Assignment(Assignment),
+    Literal(Literal),
+    Identifier(Identifier),
+    BinaryExpression(BinaryExpression),
+    UnaryExpression(UnaryExpression),
+    CallExpression(CallExpression),
+    IfStatement(IfStatement),
+    WhileStatement(WhileStatement),
+    Error(ErrorNode),
+}
+
+impl Node {
+    pub fn location(&self) -> Location {
+        match self {
+            Node::FunctionDeclaration(node) => node.location,
+            Node::VariableDeclaration(node) => node.location,
+            Node::Assignment(node) => node.location,
+            Node::Literal(node) => node.location,
+            Node::Identifier(node) => node.location,
+            Node::BinaryExpression(node) => node.location,
+            Node::UnaryExpression(node) => node.location,
+            Node::CallExpression(node) => node.location,
+            Node::IfStatement(node) => node.location,
+            Node::WhileStatement(node) => node.location,
+            Node::Error(node) => node.location,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Program {
+    pub program : Vec<Node>,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Block {
+    pub body : Vec<Node>,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identifier {
+    pub name : String,
+    pub location : Location,
+}
+
+// body is None for a declaration with an empty `{...}` body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionDeclaration {
+    pub id : Identifier,
+    pub params : Vec<Identifier>,
+    pub body : Option<Block>,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariableDeclaration {
+    pub id : Identifier,
+    pub init : Box<Node>,
+    pub location : Location,
+}
+
+// `x = e`, `x += e` and friends, value is None for `x++` and `x--`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Assignment {
+    pub id : Identifier,
+    pub operator : Operator,
+    pub value : Option<Box<Node>>,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum LiteralKind {
+    Number,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Literal {
+    pub kind : LiteralKind,
+    pub raw : String,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BinaryExpression {
+    pub left : Box<Node>,
+    pub right : Box<Node>,
+    pub operator : Operator,
+    pub location : Location,
+}
+
+// operator is Subtract for a unary minus or Not
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnaryExpression {
+    pub operand : Box<Node>,
+    pub operator : Operator,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CallExpression {
+    pub callee : Identifier,
+    pub arguments : Vec<Node>,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IfStatement {
+    pub test : Box<Node>,
+    pub consequent : Block,
+    pub alternate : Option<Block>, //an `else if` is a block holding a single IfStatement
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WhileStatement {
+    pub test : Box<Node>,
+    pub body : Block,
+    pub location : Location,
+}
+
+// Stands in for a statement that failed to parse, covers the tokens skipped while recovering
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorNode {
+    pub location : Location,
+}
+
+// Every operator the lexer can emit, see Lexer::match_operator
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Exp,
+    Assign,
+    AddEquals,
+    MinusEquals,
+    TimesEquals,
+    DivideEquals,
+    Increment,
+    Decrement,
+    Equals,
+    SmallerEquals,
+    GreaterEquals,
+    Smaller,
+    Greater,
+    Or,
+    And,
+    Not
+}
+
+impl Operator {
+    pub fn from_text(text: &str) -> Option<Operator> {
+        match text {
+            "+" => Some(Operator::Add),
+            "-" => Some(Operator::Subtract),
+            "*" => Some(Operator::Multiply),
+            "/" => Some(Operator::Divide),
+            "^" => Some(Operator::Exp),
+            "=" => Some(Operator::Assign),
+            "+=" => Some(Operator::AddEquals),
+            "-=" => Some(Operator::MinusEquals),
+            "*=" => Some(Operator::TimesEquals),
+            "/=" => Some(Operator::DivideEquals),
+            "++" => Some(Operator::Increment),
+            "--" => Some(Operator::Decrement),
+            "==" => Some(Operator::Equals),
+            "<=" => Some(Operator::SmallerEquals),
+            ">=" => Some(Operator::GreaterEquals),
+            "<" => Some(Operator::Smaller),
+            ">" => Some(Operator::Greater),
+            "or" => Some(Operator::Or),
+            "and" => Some(Operator::And),
+            "not" => Some(Operator::Not),
+            _ => None,
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Operator::Equals | Operator::SmallerEquals | Operator::GreaterEquals | Operator::Smaller | Operator::Greater)
+    }
+
+    pub fn is_assignment(&self) -> bool {
+        matches!(self, Operator::Assign | Operator::AddEquals | Operator::MinusEquals | Operator::TimesEquals
+            | Operator::DivideEquals | Operator::Increment | Operator::Decrement)
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op_str = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Exp => "^",
+            Operator::Assign => "=",
+            Operator::AddEquals => "+=",
+            Operator::MinusEquals => "-=",
+            Operator::TimesEquals => "*=",
+            Operator::DivideEquals => "/=",
+            Operator::Increment => "++",
+            Operator::Decrement => "--",
+            Operator::Equals => "==",
+            Operator::SmallerEquals => "<=",
+            Operator::GreaterEquals => ">=",
+            Operator::Smaller => "<",
+            Operator::Greater => ">",
+            Operator::Or => "or",
+            Operator::And => "and",
+            Operator::Not => "not",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum ParseErrorKind {
+    Expected { expected: String, found: String },
+    Unclosed { delimiter: String, found: String, open: Span },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseError {
+    pub kind : ParseErrorKind,
+    pub length : usize, //in chars
+    pub position : Position,
+    pub span : Span
+}
+
+fn describe(token: &Token) -> String {
+    match token.kind {
+        TokenKind::EOF => "end of input".to_string(),
+        _ => format!("'{}'", token.text),
+    }
+}
+
+impl ParseError {
+    // The error points at the offending token
+    pub fn at(kind: ParseErrorKind, token: &Token) -> ParseError {
+        ParseError { kind, length: token.length, position: token.position, span: token.span }
+    }
+
+    pub fn expected(expected: &str, token: &Token) -> ParseError {
+        ParseError::at(ParseErrorKind::Expected { expected: expected.to_string(), found: describe(token) }, token)
+    }
+
+    // A closing delimiter is missing, open is the token that needed closing
+    pub fn unclosed(delimiter: &str, open: &Token, token: &Token) -> ParseError {
+        ParseError::at(ParseErrorKind::Unclosed { delimiter: delimiter.to_string(), found: describe(token), open: open.span }, token)
+    }
+
+    pub fn message(&self) -> String {
+        match &self.kind {
+            ParseErrorKind::Expected { expected, found } => format!("expected {} but found {}", expected, found),
+            ParseErrorKind::Unclosed { delimiter, found, .. } => format!("expected '{}' but found {}", delimiter, found),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(&self.message(), self.span);
+        match &self.kind {
+            ParseErrorKind::Expected { expected, .. } => diagnostic.with_primary_label(&format!("expected {}", expected)),
+            ParseErrorKind::Unclosed { delimiter, open, .. } => diagnostic
+                .with_primary_label(&format!("expected '{}'", delimiter))
+                .with_label(*open, "unclosed delimiter opened here"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at row {}, column {}", self.message(), self.position.row + 1, self.position.col + 1)
+    }
+}
@@ -0,0 +1,380 @@
+
+use crate::dense::{DenseTable, RingTape};
+use crate::history::History;
+use crate::transition_types::*;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+// A tape that is blank in both directions
+// cell i >= 0 lives in right[i], cell i < 0 lives in left[-i - 1]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tape {
+    left : Vec<String>,
+    right : Vec<String>,
+    head : i64
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape { left: Vec::new(), right: Vec::new(), head: 0 }
+    }
+
+    // Every char of the input is one symbol, the head starts on the first one
+    pub fn from_input(input: &str) -> Tape {
+        Tape::from_symbols(input.chars().map(|c| c.to_string()).collect())
+    }
+
+    pub fn from_symbols(symbols: Vec<String>) -> Tape {
+        Tape { left: Vec::new(), right: symbols, head: 0 }
+    }
+
+    // symbols start at cell lowest, blank ones are skipped
+    pub fn at<'a>(lowest: i64, symbols: impl Iterator<Item = &'a str>, head: i64) -> Tape {
+        let mut tape = Tape::new();
+        for (offset, symbol) in symbols.enumerate() {
+            tape.head = lowest + offset as i64;
+            tape.write(symbol);
+        }
+        tape.head = head;
+        tape
+    }
+
+    pub fn get(&self, index: i64) -> &str {
+        let cell = if index >= 0 {
+            self.right.get(index as usize)
+        } else {
+            self.left.get((-index - 1) as usize)
+        };
+        cell.map(|s| s.as_str()).unwrap_or(BLANK)
+    }
+
+    pub fn read(&self) -> &str {
+        self.get(self.head)
+    }
+
+    pub fn head(&self) -> i64 {
+        self.head
+    }
+
+    pub fn write(&mut self, symbol: &str) {
+        let (cells, index) = if self.head >= 0 {
+            (&mut self.right, self.head as usize)
+        } else {
+            (&mut self.left, (-self.head - 1) as usize)
+        };
+        if index >= cells.len() {
+            if symbol == BLANK {
+                return;
+            }
+            cells.resize(index + 1, BLANK.to_string());
+        }
+        cells[index] = symbol.to_string();
+    }
+
+    pub fn shift(&mut self, direction: Direction) {
+        match direction {
+            Direction::Left => self.head -= 1,
+            Direction::Right => self.head += 1,
+            Direction::Stay => {}
+        }
+    }
+
+    // Index range of the non-blank part of the tape, None when the tape is blank
+    pub fn bounds(&self) -> Option<(i64, i64)> {
+        let lowest = self.left.iter().rposition(|s| s != BLANK).map(|i| -(i as i64) - 1)
+            .or_else(|| self.right.iter().position(|s| s != BLANK).map(|i| i as i64))?;
+        let highest = self.right.iter().rposition(|s| s != BLANK).map(|i| i as i64)
+            .or_else(|| self.left.iter().position(|s| s != BLANK).map(|i| -(i as i64) - 1))?;
+        Some((lowest, highest))
+    }
+
+    // The non-blank symbols from left to right
+    pub fn symbols(&self) -> Vec<&str> {
+        match self.bounds() {
+            Some((lowest, highest)) => (lowest..=highest).map(|i| self.get(i)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Tape::new()
+    }
+}
+
+impl fmt::Display for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbols().concat())
+    }
+}
+
+impl Serialize for Tape {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Tape", 3)?;
+        state.serialize_field("offset", &self.bounds().map(|(lowest, _)| lowest).unwrap_or(0))?;
+        state.serialize_field("cells", &self.symbols())?;
+        state.serialize_field("head", &self.head)?;
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Halt {
+    Accept,
+    Reject,
+    NoTransition
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Moved,
+    Halted(Halt)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub halt : Option<Halt>, //None when the step budget ran out first
+    pub steps : usize,
+    pub state : String,
+    pub tapes : Vec<Tape>
+}
+
+pub const ACCEPT_STATES: [&str; 2] = ["accept", "ACCEPT"];
+pub const REJECT_STATES: [&str; 2] = ["reject", "REJECT"];
+
+// Writes and moves every tape as the transition says
+pub fn apply(tapes: &mut [Tape], transition: &Transition) {
+    for (index, tape) in tapes.iter_mut().enumerate() {
+        tape.write(&transition.write[index]);
+        tape.shift(transition.directions[index]);
+    }
+}
+
+// Runs a table on as many tapes as its transitions have, each with its own head.
+// States and symbols are interned into a DenseTable so a step never touches a string
+pub struct Machine {
+    table: DenseTable,
+    transitions: Vec<Transition>, //the dense table's transitions as they were written
+    state: u32,
+    tapes: Vec<RingTape>,
+    steps: usize,
+    history: Option<History>, //only kept when going back is wanted
+}
+
+impl Machine {
+    // When a (state, symbols) pair occurs more than once only the first transition is ever taken,
+    // explorer::explore follows every choice. Tapes that aren't given start out blank
+    pub fn new(table: &TransitionTable, tapes: Vec<Tape>) -> Machine {
+        let count = tapes.len().max(table.tapes());
+        let input: Vec<&str> = tapes.iter().flat_map(Tape::symbols).collect();
+        let dense = DenseTable::new(table, count, &input);
+        let mut rings: Vec<RingTape> = tapes.iter().map(|tape| {
+            let symbols: Vec<u32> = tape.symbols().iter().map(|symbol| dense.symbols.id(symbol).expect("input symbols are interned")).collect();
+            RingTape::new(tape.bounds().map(|(lowest, _)| lowest).unwrap_or(0), &symbols, tape.head())
+        }).collect();
+        rings.resize(count, RingTape::new(0, &[], 0));
+
+        Machine {
+            transitions: dense.source.iter().map(|&index| table.transitions[index].clone()).collect(),
+            state: dense.states.id(&table.start).expect("the start state is interned"),
+            table: dense,
+            tapes: rings,
+            steps: 0,
+            history: None,
+        }
+    }
+
+    pub fn state(&self) -> &str {
+        self.table.states.name(self.state)
+    }
+
+    pub fn tape_count(&self) -> usize {
+        self.tapes.len()
+    }
+
+    // The symbol under a head
+    pub fn read(&self, tape: usize) -> &str {
+        self.table.symbols.name(self.tapes[tape].read())
+    }
+
+    pub fn cell(&self, tape: usize, index: i64) -> &str {
+        self.table.symbols.name(self.tapes[tape].get(index))
+    }
+
+    // A copy of the tapes as they are now
+    pub fn tapes(&self) -> Vec<Tape> {
+        self.tapes.iter().map(|ring| {
+            let (lowest, cells) = ring.held();
+            Tape::at(lowest, cells.map(|symbol| self.table.symbols.name(symbol)), ring.head())
+        }).collect()
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn transition(&self) -> Option<u32> {
+        self.table.lookup(self.state, self.tapes.iter().map(RingTape::read))
+    }
+
+    // Why the machine stops in its current configuration, None while it can still move
+    pub fn halted(&self) -> Option<Halt> {
+        match self.table.halts(self.state) {
+            Some(halt) => Some(halt),
+            None if self.transition().is_none() => Some(Halt::NoTransition),
+            None => None,
+        }
+    }
+
+    // The transition the next step takes, None once the machine has halted
+    pub fn next_transition(&self) -> Option<&Transition> {
+        match self.table.halts(self.state) {
+            Some(_) => None,
+            None => self.transition().map(|id| &self.transitions[id as usize]),
+        }
+    }
+
+    pub fn step(&mut self) -> Step {
+        if let Some(halt) = self.table.halts(self.state) {
+            return Step::Halted(halt);
+        }
+        let Some(id) = self.transition() else {
+            return Step::Halted(Halt::NoTransition);
+        };
+        if let Some(history) = &mut self.history {
+            history.record(self.steps, self.state, &self.tapes, id);
+        }
+        let first = id as usize * self.tapes.len();
+        for (index, tape) in self.tapes.iter_mut().enumerate() {
+            tape.write(self.table.write[first + index]);
+            tape.shift(self.table.moves[first + index]);
+        }
+        self.state = self.table.next[id as usize];
+        self.steps += 1;
+        Step::Moved
+    }
+
+    // Records every step from here on, so jump and step_back can return to them
+    pub fn keep_history(&mut self) {
+        self.history = Some(History::new());
+    }
+
+    // Goes back to an earlier step, or runs on to a later one unless the machine halts first
+    pub fn jump(&mut self, step: usize) -> Result<(), String> {
+        while self.steps < step && self.halted().is_none() {
+            self.step();
+        }
+        if self.steps <= step {
+            return Ok(());
+        }
+
+        let history = self.history.as_mut().ok_or("the machine keeps no history")?;
+        if self.steps - step <= history.undoable() {
+            for _ in step..self.steps {
+                history.undo(&mut self.state, &mut self.tapes, &self.table);
+            }
+            self.steps = step;
+            return Ok(());
+        }
+        let checkpoint = history.restore(step).ok_or_else(|| format!("step {} is no longer in the history", step))?;
+        self.state = checkpoint.state;
+        self.tapes = checkpoint.tapes;
+        self.steps = checkpoint.steps;
+        self.jump(step)
+    }
+
+    pub fn step_back(&mut self, count: usize) -> Result<(), String> {
+        match self.steps.checked_sub(count) {
+            Some(step) => self.jump(step),
+            None => Err(format!("there are only {} steps to go back", self.steps)),
+        }
+    }
+
+    pub fn run(&mut self, max_steps: usize) -> RunReport {
+        self.run_observed(max_steps, |_| {})
+    }
+
+    // observe sees every configuration before the step taken from it, and the one the run ends in
+    pub fn run_observed(&mut self, max_steps: usize, mut observe: impl FnMut(&Machine)) -> RunReport {
+        let mut halt = None;
+        for _ in 0..max_steps {
+            observe(self);
+            if let Step::Halted(reason) = self.step() {
+                halt = Some(reason);
+                break;
+            }
+        }
+        // a machine that halts exactly on its last allowed step still counts as halted
+        if halt.is_none() {
+            observe(self);
+            halt = self.halted();
+        }
+
+        RunReport { halt, steps: self.steps, state: self.state().to_string(), tapes: self.tapes() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transition_reader::TableReader;
+
+    // Runs over the ones and halts in end on the first blank
+    fn machine(end: &str, input: &str) -> Machine {
+        let table = TableReader::new(&format!("q 1 1 right q\nq _ _ stay {}", end)).read().unwrap();
+        Machine::new(&table, vec![Tape::from_input(input)])
+    }
+
+    #[test]
+    fn reports_why_a_machine_halts() {
+        let report = machine("accept", "11").run(100);
+        assert_eq!((report.halt, report.steps, report.state.as_str()), (Some(Halt::Accept), 3, "accept"));
+        let report = machine("REJECT", "11").run(100);
+        assert_eq!((report.halt, report.steps, report.state.as_str()), (Some(Halt::Reject), 3, "REJECT"));
+        // q has nothing to do on a 0
+        let report = machine("accept", "110").run(100);
+        assert_eq!((report.halt, report.steps, report.state.as_str()), (Some(Halt::NoTransition), 2, "q"));
+        assert_eq!(report.tapes[0].to_string(), "110");
+
+        let mut halted = machine("accept", "");
+        assert_eq!(halted.step(), Step::Moved);
+        assert_eq!(halted.step(), Step::Halted(Halt::Accept));
+        assert_eq!(halted.steps(), 1);
+    }
+
+    #[test]
+    fn stops_when_the_step_budget_runs_out() {
+        let report = machine("accept", "1111").run(2);
+        assert_eq!((report.halt, report.steps, report.state.as_str()), (None, 2, "q"));
+        assert_eq!(report.tapes[0].head(), 2);
+        // the third step enters accept, which still counts as halting within 3 steps
+        let report = machine("accept", "11").run(3);
+        assert_eq!((report.halt, report.steps), (Some(Halt::Accept), 3));
+        assert_eq!(machine("accept", "11").run(0).halt, None);
+    }
+
+    #[test]
+    fn tapes_extend_left_of_cell_zero() {
+        let mut tape = Tape::from_input("ab");
+        tape.shift(Direction::Left);
+        tape.shift(Direction::Left);
+        tape.write("x");
+        assert_eq!(tape.head(), -2);
+        assert_eq!(tape.bounds(), Some((-2, 1)));
+        assert_eq!(tape.symbols(), ["x", BLANK, "a", "b"]);
+        assert_eq!(tape.to_string(), "x_ab");
+        assert_eq!(tape.get(-3), BLANK);
+        // writing a blank where nothing was written leaves the bounds alone
+        tape.shift(Direction::Left);
+        tape.write(BLANK);
+        assert_eq!(tape.bounds(), Some((-2, 1)));
+
+        let table = TableReader::new("q a b left q\nq _ c stay accept").read().unwrap();
+        let report = Machine::new(&table, vec![Tape::from_input("a")]).run(10);
+        assert_eq!(report.tapes[0].bounds(), Some((-1, 0)));
+        assert_eq!(report.tapes[0].to_string(), "cb");
+        assert_eq!(Tape::new().bounds(), None);
+    }
+}
@@ -0,0 +1,417 @@
+
+use crate::codegen_types::*;
+use crate::ir_types::Encoding;
+use crate::transition_types::*;
+
+// A piece of a machine with one entry and a few named exits, like the README's GO_TOTAL_RIGHT.
+//
+// States are named locally: the machine starts in ENTRY and leaves through states starting with '@', such as
+// DONE. Including a sub-machine in another one prefixes its states with the name the parent gives it, so every
+// instance gets its own states and a name like `bb3.1.append.home` says exactly where a state comes from.
+// Unless said otherwise a sub-machine starts and ends with the head on a marker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubMachine {
+    pub name : String,
+    pub transitions : Vec<Transition>,
+}
+
+impl SubMachine {
+    pub fn new(name: &str) -> SubMachine {
+        SubMachine { name: name.to_string(), transitions: Vec::new() }
+    }
+
+    pub fn add(&mut self, state: &str, read: &str, write: &str, direction: Direction, next: &str) {
+        self.transitions.push(Transition::new(state, read, write, direction, next));
+    }
+
+    // Inlines a child whose ENTRY is `entry` and whose exits lead to the states `exits` maps them to
+    pub fn include(&mut self, prefix: &str, child: &SubMachine, entry: &str, exits: &[(&str, &str)]) {
+        let rename = |state: &str| -> String {
+            if state == ENTRY {
+                entry.to_string()
+            } else if state.starts_with('@') {
+                match exits.iter().find(|(exit, _)| *exit == state) {
+                    Some((_, target)) => target.to_string(),
+                    None => panic!("exit {} of {} isn't connected", state, child.name),
+                }
+            } else {
+                format!("{}.{}", prefix, state)
+            }
+        };
+
+        for transition in &child.transitions {
+            let state = rename(&transition.state);
+            let next = rename(&transition.next);
+            self.transitions.push(Transition::multi(&state, transition.read.clone(), transition.write.clone(), transition.directions.clone(), &next));
+        }
+    }
+
+    // Includes a child that only has DONE as exit
+    pub fn then(&mut self, prefix: &str, child: &SubMachine, entry: &str, done: &str) {
+        self.include(prefix, child, entry, &[(DONE, done)]);
+    }
+}
+
+// Moves on without doing anything
+pub fn goto() -> SubMachine {
+    let mut machine = SubMachine::new("goto");
+    machine.add(ENTRY, MARKER, MARKER, Direction::Stay, DONE);
+    machine
+}
+
+// Writes the cells from the head onwards on a blank tape and comes back to the first one
+pub fn write(alphabet: &Alphabet, cells: &[&str]) -> SubMachine {
+    let mut machine = SubMachine::new("write");
+    let mut state = ENTRY.to_string();
+    for (i, cell) in cells.iter().enumerate() {
+        let next = format!("cell_{}", i);
+        machine.add(&state, BLANK, cell, Direction::Right, &next);
+        state = next;
+    }
+    machine.add(&state, BLANK, BLANK, Direction::Left, "back");
+    machine.then("home", &home(alphabet), "back", DONE);
+    machine
+}
+
+// Moves right until it reads `symbol`, not counting the cell it starts on
+pub fn scan_right_to(alphabet: &Alphabet, symbol: &str) -> SubMachine {
+    scan(alphabet, symbol, Direction::Right, &format!("scan_right_to({})", symbol))
+}
+
+pub fn scan_left_to(alphabet: &Alphabet, symbol: &str) -> SubMachine {
+    scan(alphabet, symbol, Direction::Left, &format!("scan_left_to({})", symbol))
+}
+
+fn scan(alphabet: &Alphabet, symbol: &str, direction: Direction, name: &str) -> SubMachine {
+    let mut machine = SubMachine::new(name);
+    for read in alphabet.symbols() {
+        machine.add(ENTRY, read, read, direction, "scan");
+        if read == symbol {
+            machine.add("scan", read, read, Direction::Stay, DONE);
+        } else {
+            machine.add("scan", read, read, direction, "scan");
+        }
+    }
+    machine
+}
+
+// Moves to the first marker from anywhere on the tape
+pub fn home(alphabet: &Alphabet) -> SubMachine {
+    let mut machine = SubMachine::new("home");
+    for read in alphabet.symbols() {
+        machine.add(ENTRY, read, read, Direction::Left, ENTRY);
+    }
+    machine.add(ENTRY, BLANK, BLANK, Direction::Right, DONE);
+    machine
+}
+
+// Moves to the marker of a region from anywhere on the tape
+pub fn seek(alphabet: &Alphabet, region: usize) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("seek({})", region));
+    let scan = scan_right_to(alphabet, MARKER);
+    let first = if region == 0 { DONE.to_string() } else { "marker_0".to_string() };
+    machine.then("home", &home(alphabet), ENTRY, &first);
+    for marker in 1..=region {
+        let next = if marker == region { DONE.to_string() } else { format!("marker_{}", marker) };
+        machine.then(&format!("scan_{}", marker), &scan, &format!("marker_{}", marker - 1), &next);
+    }
+    machine
+}
+
+// Writes `symbol` under the head and moves every cell from there on one to the right,
+// ends on what used to be the last cell of the tape
+pub fn insert(alphabet: &Alphabet, symbol: &str) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("insert({})", symbol));
+    let carry = |symbol: &str| format!("carry_{}", symbol);
+    for read in alphabet.symbols() {
+        machine.add(ENTRY, read, symbol, Direction::Right, &carry(read));
+    }
+    for carried in alphabet.symbols() {
+        for read in alphabet.symbols() {
+            machine.add(&carry(carried), read, carried, Direction::Right, &carry(read));
+        }
+        machine.add(&carry(carried), BLANK, carried, Direction::Stay, DONE);
+    }
+    machine
+}
+
+// Removes the cell under the head and moves every cell after it one to the left, ends where the cell was
+pub fn delete(alphabet: &Alphabet) -> SubMachine {
+    let mut machine = SubMachine::new("delete");
+    let pull = |symbol: &str| format!("pull_{}", symbol);
+    for read in alphabet.symbols() {
+        machine.add(ENTRY, read, DELETED, Direction::Right, "end");
+        machine.add("end", read, read, Direction::Right, "end");
+        machine.add("last", read, BLANK, Direction::Left, &pull(read));
+    }
+    machine.add("end", BLANK, BLANK, Direction::Left, "last");
+    // the pulled symbol lands on the marked cell
+    for pulled in alphabet.symbols() {
+        for read in alphabet.symbols() {
+            machine.add(&pull(pulled), read, pulled, Direction::Left, &pull(read));
+        }
+        machine.add(&pull(pulled), DELETED, pulled, Direction::Stay, DONE);
+    }
+    machine
+}
+
+// Leaves through ZERO when the region the head is on is empty and NONZERO otherwise
+pub fn test_zero(alphabet: &Alphabet) -> SubMachine {
+    let mut machine = SubMachine::new("test_zero");
+    machine.add(ENTRY, MARKER, MARKER, Direction::Right, "look");
+    machine.add("look", MARKER, MARKER, Direction::Left, ZERO);
+    for digit in &alphabet.digits {
+        machine.add("look", digit, digit, Direction::Left, NONZERO);
+    }
+    machine
+}
+
+// Empties the region one cell at a time
+pub fn clear(alphabet: &Alphabet) -> SubMachine {
+    let mut machine = SubMachine::new("clear");
+    machine.add(ENTRY, MARKER, MARKER, Direction::Right, "check");
+    machine.add("check", MARKER, MARKER, Direction::Left, DONE);
+    for digit in &alphabet.digits {
+        machine.add("check", digit, digit, Direction::Stay, "delete");
+    }
+    machine.then("delete", &delete(alphabet), "delete", "back");
+    for read in alphabet.symbols() {
+        machine.add("back", read, read, Direction::Left, ENTRY);
+    }
+    machine
+}
+
+// Marks every digit of the source in turn and appends it to the destination, then undoes the marks.
+// Both regions need the same encoding, starts and ends on the source
+pub fn copy(alphabet: &Alphabet, encoding: Encoding, from: usize, to: usize) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("copy({}, {})", from, to));
+    machine.then("to", &seek(alphabet, to), ENTRY, "clear");
+    machine.then("clear", &clear(alphabet), "clear", "from");
+    machine.then("from", &seek(alphabet, from), "from", "mark");
+
+    machine.add("mark", MARKER, MARKER, Direction::Right, "find");
+    for &digit in digits(encoding) {
+        let append = format!("append_{}", digit);
+        machine.add("find", Alphabet::mark(digit), Alphabet::mark(digit), Direction::Right, "find");
+        machine.add("find", digit, Alphabet::mark(digit), Direction::Left, &append);
+        machine.then(&append, &seek(alphabet, to), &append, &format!("{}.end", append));
+        machine.then(&format!("{}.end", append), &scan_right_to(alphabet, MARKER), &format!("{}.end", append), &format!("{}.insert", append));
+        machine.then(&format!("{}.insert", append), &insert(alphabet, digit), &format!("{}.insert", append), &format!("{}.return", append));
+        machine.then(&format!("{}.return", append), &seek(alphabet, from), &format!("{}.return", append), "mark");
+        machine.add("restore", Alphabet::mark(digit), digit, Direction::Left, "restore");
+    }
+    machine.add("find", MARKER, MARKER, Direction::Left, "restore");
+    machine.add("restore", MARKER, MARKER, Direction::Stay, DONE);
+    machine
+}
+
+pub fn increment(alphabet: &Alphabet, encoding: Encoding, region: usize) -> SubMachine {
+    match encoding {
+        Encoding::Unary => unary_increment(alphabet, region),
+        Encoding::BinaryLittleEndian => little_endian_increment(alphabet, digits(encoding), region),
+        Encoding::BinaryBigEndian | Encoding::Decimal => big_endian_increment(alphabet, digits(encoding), region),
+    }
+}
+
+pub fn decrement(alphabet: &Alphabet, encoding: Encoding) -> SubMachine {
+    match encoding {
+        Encoding::Unary => unary_decrement(alphabet),
+        Encoding::BinaryLittleEndian => little_endian_decrement(alphabet, digits(encoding)),
+        Encoding::BinaryBigEndian | Encoding::Decimal => big_endian_decrement(alphabet, digits(encoding)),
+    }
+}
+
+// Appends a one to the region by inserting it before the next marker
+pub fn unary_increment(alphabet: &Alphabet, region: usize) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("unary_increment({})", region));
+    machine.then("end", &scan_right_to(alphabet, MARKER), ENTRY, "insert");
+    machine.then("insert", &insert(alphabet, DIGITS[1]), "insert", "back");
+    machine.then("back", &seek(alphabet, region), "back", DONE);
+    machine
+}
+
+// Removes the first one of the region, an empty region stays empty
+pub fn unary_decrement(alphabet: &Alphabet) -> SubMachine {
+    let mut machine = SubMachine::new("unary_decrement");
+    machine.add(ENTRY, MARKER, MARKER, Direction::Right, "check");
+    machine.add("check", MARKER, MARKER, Direction::Left, DONE);
+    machine.add("check", DIGITS[1], DIGITS[1], Direction::Stay, "delete");
+    machine.then("delete", &delete(alphabet), "delete", "back");
+    machine.then("back", &scan_left_to(alphabet, MARKER), "back", DONE);
+    machine
+}
+
+// Adds one to a number written least significant digit first, a carry out of the last digit grows the region
+pub fn little_endian_increment(alphabet: &Alphabet, digits: &[&str], region: usize) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("little_endian_increment(base {}, {})", digits.len(), region));
+    machine.add(ENTRY, MARKER, MARKER, Direction::Right, "carry");
+    add_one(&mut machine, digits, Direction::Right);
+    machine.then("grow", &insert(alphabet, digits[1]), "grow", "reseek");
+    machine.then("reseek", &seek(alphabet, region), "reseek", DONE);
+    machine.then("back", &scan_left_to(alphabet, MARKER), "back", DONE);
+    machine
+}
+
+// Adds one to a number written most significant digit first, a carry out of the first digit grows the region
+pub fn big_endian_increment(alphabet: &Alphabet, digits: &[&str], region: usize) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("big_endian_increment(base {}, {})", digits.len(), region));
+    machine.then("end", &scan_right_to(alphabet, MARKER), ENTRY, "last");
+    machine.add("last", MARKER, MARKER, Direction::Left, "carry");
+    add_one(&mut machine, digits, Direction::Left);
+    // the carry reached our own marker, the new digit goes right after it
+    machine.then("grow", &insert(alphabet, digits[1]), "grow", "reseek");
+    machine.then("reseek", &seek(alphabet, region), "reseek", DONE);
+    machine.then("back", &scan_left_to(alphabet, MARKER), "back", DONE);
+    machine
+}
+
+// "carry" adds one to the digit under the head, moving on to the next digit in `direction` while it overflows.
+// Leaves to "back" on the last digit it changed or to "grow" on the cell a new digit has to go
+fn add_one(machine: &mut SubMachine, digits: &[&str], direction: Direction) {
+    for (i, digit) in digits.iter().enumerate() {
+        match digits.get(i + 1) {
+            Some(next) => machine.add("carry", digit, next, Direction::Stay, "back"),
+            None => machine.add("carry", digit, digits[0], direction, "carry"),
+        }
+    }
+    match direction {
+        Direction::Left => machine.add("carry", MARKER, MARKER, Direction::Right, "grow"),
+        _ => machine.add("carry", MARKER, MARKER, Direction::Stay, "grow"),
+    }
+}
+
+// Takes one from a number written least significant digit first, a most significant digit that drops to 0 is removed
+pub fn little_endian_decrement(alphabet: &Alphabet, digits: &[&str]) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("little_endian_decrement(base {})", digits.len()));
+    machine.add(ENTRY, MARKER, MARKER, Direction::Right, "borrow");
+    machine.add("borrow", MARKER, MARKER, Direction::Left, DONE);
+    take_one(&mut machine, digits, Direction::Right);
+    // the digit that dropped to 0 was the most significant one when a marker follows it
+    machine.add("check", MARKER, MARKER, Direction::Left, "strip");
+    for digit in digits {
+        machine.add("check", digit, digit, Direction::Left, "back");
+    }
+    machine.then("strip", &delete(alphabet), "strip", "back");
+    machine.then("back", &scan_left_to(alphabet, MARKER), "back", DONE);
+    machine
+}
+
+// Takes one from a number written most significant digit first, a most significant digit that drops to 0 is removed
+pub fn big_endian_decrement(alphabet: &Alphabet, digits: &[&str]) -> SubMachine {
+    let mut machine = SubMachine::new(&format!("big_endian_decrement(base {})", digits.len()));
+    machine.then("end", &scan_right_to(alphabet, MARKER), ENTRY, "last");
+    machine.add("last", MARKER, MARKER, Direction::Left, "borrow");
+    // only an empty region borrows all the way back to its own marker
+    machine.add("borrow", MARKER, MARKER, Direction::Stay, DONE);
+    take_one(&mut machine, digits, Direction::Left);
+    // the digit that dropped to 0 was the most significant one when the marker comes before it
+    machine.add("check", MARKER, MARKER, Direction::Right, "strip");
+    for digit in digits {
+        machine.add("check", digit, digit, Direction::Stay, "back");
+    }
+    machine.then("strip", &delete(alphabet), "strip", "back");
+    machine.then("back", &scan_left_to(alphabet, MARKER), "back", DONE);
+    machine
+}
+
+// "borrow" takes one from the digit under the head, moving on to the next digit in `direction` while it is 0.
+// A digit that drops to 0 moves on in `direction` to "check", any other change leaves to "back"
+fn take_one(machine: &mut SubMachine, digits: &[&str], direction: Direction) {
+    let last = digits[digits.len() - 1];
+    for (i, digit) in digits.iter().enumerate() {
+        match i {
+            0 => machine.add("borrow", digit, last, direction, "borrow"),
+            1 => machine.add("borrow", digit, digits[0], direction, "check"),
+            _ => machine.add("borrow", digit, digits[i - 1], Direction::Stay, "back"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Halt, Machine, Tape};
+
+    // Runs a sub-machine on a tape with the head on its first cell, every exit halts in a state named after it
+    fn run(machine: &SubMachine, tape: &str) -> (String, String) {
+        let exits = [(DONE, "accept"), (ZERO, "zero"), (NONZERO, "nonzero")];
+        let mut program = SubMachine::new("test");
+        program.include("m", machine, "start", &exits);
+        let table = TransitionTable { start: "start".to_string(), transitions: program.transitions };
+
+        let report = Machine::new(&table, vec![Tape::from_input(tape)]).run(100_000);
+        // zero and nonzero have no transitions, so stopping there is taking that exit rather than getting stuck
+        let exited = report.halt == Some(Halt::Accept) || ["zero", "nonzero"].contains(&report.state.as_str());
+        assert!(exited, "{} got stuck in {}", machine.name, report.state);
+        (report.state, report.tapes[0].to_string())
+    }
+
+    // Seeks the region first, then runs the machine
+    fn on_region(alphabet: &Alphabet, region: usize, machine: &SubMachine) -> SubMachine {
+        let mut composed = SubMachine::new(&machine.name);
+        composed.then("seek", &seek(alphabet, region), ENTRY, "run");
+        composed.include("run", machine, "run", &[(DONE, DONE), (ZERO, ZERO), (NONZERO, NONZERO)]);
+        composed
+    }
+
+    fn increments(encoding: Encoding, tape: &str) -> String {
+        let alphabet = Alphabet::new(&[encoding]);
+        run(&on_region(&alphabet, 1, &increment(&alphabet, encoding, 1)), tape).1
+    }
+
+    fn decrements(encoding: Encoding, tape: &str) -> String {
+        let alphabet = Alphabet::new(&[encoding]);
+        run(&on_region(&alphabet, 1, &decrement(&alphabet, encoding)), tape).1
+    }
+
+    #[test]
+    fn unary_increment_shifts_the_regions_after_it() {
+        assert_eq!(increments(Encoding::Unary, "#11##1#"), "#11#1#1#");
+        assert_eq!(increments(Encoding::Unary, "##1#1#"), "##11#1#");
+    }
+
+    #[test]
+    fn positional_increment_carries_and_grows() {
+        assert_eq!(increments(Encoding::BinaryLittleEndian, "#1#11#1#"), "#1#001#1#");
+        assert_eq!(increments(Encoding::BinaryLittleEndian, "#1#01#1#"), "#1#11#1#");
+        assert_eq!(increments(Encoding::BinaryBigEndian, "#1#11#1#"), "#1#100#1#");
+        assert_eq!(increments(Encoding::BinaryBigEndian, "#1##1#"), "#1#1#1#");
+        assert_eq!(increments(Encoding::Decimal, "#1#199#1#"), "#1#200#1#");
+    }
+
+    #[test]
+    fn decrement_drops_leading_zeros_and_stops_at_zero() {
+        assert_eq!(decrements(Encoding::Unary, "#1#11#"), "#1#1#");
+        assert_eq!(decrements(Encoding::BinaryLittleEndian, "#1#001#1#"), "#1#11#1#");
+        assert_eq!(decrements(Encoding::BinaryLittleEndian, "#1#1#1#"), "#1##1#");
+        assert_eq!(decrements(Encoding::BinaryBigEndian, "#1#100#1#"), "#1#11#1#");
+        assert_eq!(decrements(Encoding::Decimal, "#1#10#1#"), "#1#9#1#");
+        assert_eq!(decrements(Encoding::Decimal, "#1##1#"), "#1##1#");
+    }
+
+    #[test]
+    fn test_zero_takes_the_matching_exit() {
+        let alphabet = Alphabet::new(&[Encoding::Unary]);
+        assert_eq!(run(&on_region(&alphabet, 1, &test_zero(&alphabet)), "#1##").0, "zero");
+        assert_eq!(run(&on_region(&alphabet, 0, &test_zero(&alphabet)), "#1##").0, "nonzero");
+    }
+
+    #[test]
+    fn copy_replaces_the_destination_and_keeps_the_source() {
+        let alphabet = Alphabet::new(&[Encoding::Unary]);
+        assert_eq!(run(&on_region(&alphabet, 2, &copy(&alphabet, Encoding::Unary, 2, 0)), "#1##111#").1, "#111##111#");
+        assert_eq!(run(&on_region(&alphabet, 0, &copy(&alphabet, Encoding::Unary, 0, 1)), "#11#1#").1, "#11#11#");
+        let alphabet = Alphabet::new(&[Encoding::Decimal]);
+        assert_eq!(run(&on_region(&alphabet, 0, &copy(&alphabet, Encoding::Decimal, 0, 1)), "#305#7#").1, "#305#305#");
+        assert_eq!(run(&on_region(&alphabet, 0, &clear(&alphabet)), "#305#7#").1, "##7#");
+    }
+
+    #[test]
+    fn included_states_are_prefixed_by_their_path() {
+        let alphabet = Alphabet::new(&[Encoding::Unary]);
+        let states: Vec<String> = copy(&alphabet, Encoding::Unary, 0, 1).transitions.iter().map(|transition| transition.state.clone()).collect();
+        assert!(states.contains(&"append_1.insert.carry_#".to_string()));
+        assert!(states.contains(&"clear.delete.end".to_string()));
+        assert!(states.iter().all(|state| state == ENTRY || !state.starts_with('@')));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::simulator::{self, Tape};
+use crate::transition_types::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines, //one JSON object per configuration
+}
+
+impl TraceFormat {
+    pub fn parse(text: &str) -> Option<TraceFormat> {
+        match text {
+            "text" => Some(TraceFormat::Text),
+            "jsonl" => Some(TraceFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+// Which configurations end up in a trace and how they are written
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceOptions {
+    pub format : TraceFormat,
+    pub states : Vec<String>, //empty for every state
+    pub every : usize, //only steps that are a multiple of it
+    pub last : Option<usize>, //only the last n configurations that pass the other filters
+    pub window : usize, //cells shown on each side of a head
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        TraceOptions { format: TraceFormat::Text, states: Vec::new(), every: 1, last: None, window: 5 }
+    }
+}
+
+// The cells around a head, start is the index of the first one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Window {
+    pub start : i64,
+    pub cells : Vec<String>,
+}
+
+// A configuration and the transition taken from it, write, moves and next are None when there is none
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceEntry {
+    pub step : usize,
+    pub state : String,
+    pub heads : Vec<i64>,
+    pub read : Vec<String>,
+    pub write : Option<Vec<String>>,
+    pub moves : Option<Vec<Direction>>,
+    pub next : Option<String>,
+    pub windows : Vec<Window>,
+}
+
+impl TraceEntry {
+    pub fn new(step: usize, state: &str, tapes: &[Tape], transition: Option<&Transition>, window: usize) -> TraceEntry {
+        let windows = tapes.iter().map(|tape| {
+            let start = tape.head() - window as i64;
+            Window { start, cells: (start..=tape.head() + window as i64).map(|index| tape.get(index).to_string()).collect() }
+        }).collect();
+        TraceEntry {
+            step,
+            state: state.to_string(),
+            heads: tapes.iter().map(Tape::head).collect(),
+            read: tapes.iter().map(|tape| tape.read().to_string()).collect(),
+            write: transition.map(|transition| transition.write.clone()),
+            moves: transition.map(|transition| transition.directions.clone()),
+            next: transition.map(|transition| transition.next.clone()),
+            windows,
+        }
+    }
+}
+
+// `      12  carry  head 3  read 1  write 0  move left  next carry  1 0 [1] 1 _`
+// with the values of every tape separated by commas and their windows by ` | `
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let heads: Vec<String> = self.heads.iter().map(|head| head.to_string()).collect();
+        write!(f, "{:>8}  {}  head {}  read {}", self.step, self.state, heads.join(","), self.read.join(","))?;
+        match (&self.write, &self.moves, &self.next) {
+            (Some(write), Some(moves), Some(next)) => {
+                let moves: Vec<String> = moves.iter().map(|direction| direction.to_string()).collect();
+                write!(f, "  write {}  move {}  next {}", write.join(","), moves.join(","), next)?;
+            }
+            _ => write!(f, "  halts")?,
+        }
+        let windows: Vec<String> = self.windows.iter().zip(&self.heads).map(|(window, head)| {
+            let cells: Vec<String> = window.cells.iter().enumerate()
+                .map(|(index, cell)| if window.start + index as i64 == *head { format!("[{}]", cell) } else { cell.clone() })
+                .collect();
+            cells.join(" ")
+        }).collect();
+        write!(f, "  {}", windows.join(" | "))
+    }
+}
+
+// Writes the configurations of a run as they pass, or keeps the last ones until the run is over.
+// The first write error stops the trace and is returned by finish
+pub struct Tracer<W: Write> {
+    options : TraceOptions,
+    out : W,
+    kept : VecDeque<TraceEntry>,
+    error : Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(options: &TraceOptions, out: W) -> Tracer<W> {
+        Tracer { options: options.clone(), out, kept: VecDeque::new(), error: None }
+    }
+
+    // Whether a configuration passes the filters, so the tapes only need to be copied for those that do
+    pub fn wants(&self, step: usize, state: &str) -> bool {
+        let states = &self.options.states;
+        (states.is_empty() || states.iter().any(|kept| kept == state)) && step.is_multiple_of(self.options.every)
+    }
+
+    pub fn record(&mut self, step: usize, state: &str, tapes: &[Tape], transition: Option<&Transition>) {
+        if !self.wants(step, state) {
+            return;
+        }
+        let entry = TraceEntry::new(step, state, tapes, transition, self.options.window);
+        match self.options.last {
+            Some(last) => {
+                self.kept.push_back(entry);
+                if self.kept.len() > last {
+                    self.kept.pop_front();
+                }
+            }
+            None => self.write(&entry),
+        }
+    }
+
+    // Retraces the path explorer::explore found from the tapes it started on
+    pub fn record_path(&mut self, mut tapes: Vec<Tape>, path: &[Transition], end: &str) {
+        if let Some(first) = path.first() {
+            tapes.resize(tapes.len().max(first.tapes()), Tape::new());
+        }
+        for (step, transition) in path.iter().enumerate() {
+            self.record(step, &transition.state, &tapes, Some(transition));
+            simulator::apply(&mut tapes, transition);
+        }
+        self.record(path.len(), end, &tapes, None);
+    }
+
+    fn write(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = match self.options.format {
+            TraceFormat::Text => entry.to_string(),
+            TraceFormat::JsonLines => serde_json::to_string(entry).expect("trace entries always serialize"),
+        };
+        if let Err(error) = writeln!(self.out, "{}", line) {
+            self.error = Some(error);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        for entry in std::mem::take(&mut self.kept) {
+            self.write(&entry);
+        }
+        match self.error {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Machine;
+    use crate::transition_reader::TableReader;
+
+    const INCREMENT: &str = "
+        right 0 0 right right
+        right 1 1 right right
+        right _ _ left  carry
+        carry 1 0 left  carry
+        carry 0 1 stay  accept
+        carry _ 1 stay  accept
+    ";
+
+    fn trace(input: &str, options: TraceOptions) -> String {
+        let table = TableReader::new(INCREMENT).read().unwrap();
+        let mut out = Vec::new();
+        let mut tracer = Tracer::new(&options, &mut out);
+        Machine::new(&table, vec![Tape::from_input(input)])
+            .run_observed(1_000, |machine| tracer.record(machine.steps(), machine.state(), &machine.tapes(), machine.next_transition()));
+        tracer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_every_configuration_as_text() {
+        let options = TraceOptions { window: 1, ..TraceOptions::default() };
+        let lines: Vec<String> = trace("1", options).lines().map(str::to_string).collect();
+        assert_eq!(lines, [
+            "       0  right  head 0  read 1  write 1  move right  next right  _ [1] _",
+            "       1  right  head 1  read _  write _  move left  next carry  1 [_] _",
+            "       2  carry  head 0  read 1  write 0  move left  next carry  _ [1] _",
+            "       3  carry  head -1  read _  write 1  move stay  next accept  _ [_] 0",
+            "       4  accept  head -1  read 1  halts  _ [1] 0",
+        ]);
+    }
+
+    #[test]
+    fn filters_states_steps_and_the_end() {
+        let steps = |options: TraceOptions| -> Vec<usize> {
+            let options = TraceOptions { format: TraceFormat::JsonLines, ..options };
+            trace("1011", options).lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["step"].as_u64().unwrap() as usize).collect()
+        };
+        assert_eq!(steps(TraceOptions { states: vec!["carry".to_string()], ..TraceOptions::default() }), [5, 6, 7]);
+        assert_eq!(steps(TraceOptions { every: 3, ..TraceOptions::default() }), [0, 3, 6]);
+        assert_eq!(steps(TraceOptions { last: Some(2), ..TraceOptions::default() }), [7, 8]);
+    }
+}
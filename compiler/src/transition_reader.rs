@@ -0,0 +1,194 @@
+
+use crate::diagnostics::SourceFile;
+use crate::lexer_types::{Position, Span};
+use crate::transition_types::*;
+
+// Reads the plain transition format from the README:
+// <current-state> <read-symbol> <write-symbol> <move-direction> <next-state>
+// A k-tape table gives k comma separated symbols and directions, `q0 a,_ a,x right,stay q1`
+// `//` starts a comment anywhere on a line, a line starting with `#` is a comment as well
+
+// Splits a line into the part holding the fields and its comment, if any
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut field_start = true;
+    let mut first_field = true;
+    for (offset, c) in line.char_indices() {
+        if c.is_whitespace() {
+            field_start = true;
+            continue;
+        }
+        if field_start {
+            if line[offset..].starts_with("//") || (first_field && c == '#') {
+                return (&line[..offset], Some(&line[offset..]));
+            }
+            first_field = false;
+        }
+        field_start = false;
+    }
+    (line, None)
+}
+
+// The text of a transition read from source, without its comment
+pub fn span(source: &SourceFile, transition: &Transition) -> Span {
+    let position = transition.position.expect("only tables read from source are reported on");
+    let line_start = source.offset(Position { row: position.row, col: 0 });
+    let (code, _) = split_comment(source.line(position.row));
+    Span { start: source.offset(position), end: line_start + code.trim_end().len() }
+}
+
+// The comma separated entries of a field with their byte offset in it
+fn entries(field: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    field.split(',').map(|entry| {
+        let start = offset;
+        offset += entry.len() + 1;
+        (start, entry)
+    }).collect()
+}
+
+pub struct TableReader<'a> {
+    content: &'a str,
+}
+
+impl<'a> TableReader<'a> {
+    pub fn new(content : &'a str) -> TableReader<'a> {
+        TableReader { content }
+    }
+
+    // Splits a line into its fields with their byte offset in the line, stopping at a comment
+    fn fields(&self, line: &'a str) -> Vec<(usize, &'a str)> {
+        let (code, _) = split_comment(line);
+        let mut fields = Vec::new();
+        let mut field_start = None;
+
+        for (offset, c) in code.char_indices().chain(std::iter::once((code.len(), ' '))) {
+            if c.is_whitespace() {
+                if let Some(start) = field_start.take() {
+                    fields.push((start, &code[start..offset]));
+                }
+            } else if field_start.is_none() {
+                field_start = Some(offset);
+            }
+        }
+        fields
+    }
+
+    fn create_error(&self, kind: TableErrorKind, line: &str, row: usize, bol: usize, start: usize, end: usize) -> TableError {
+        TableError {
+            kind,
+            length: line[start..end].chars().count(),
+            position: Position { row, col: line[..start].chars().count() },
+            span: Span { start: bol + start, end: bol + end }
+        }
+    }
+
+    // Reads every line, resuming after malformed ones so all errors are reported at once
+    pub fn read(&self) -> Result<TransitionTable, Vec<TableError>> {
+        let mut table = TransitionTable::default();
+        let mut errors = Vec::new();
+        let mut bol = 0;
+
+        for (row, line) in self.content.split('\n').enumerate() {
+            let fields = self.fields(line);
+            let line_bol = bol;
+            bol += line.len() + 1;
+
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() != 5 {
+                let (start, _) = fields[0];
+                let (last_start, last) = fields[fields.len() - 1];
+                errors.push(self.create_error(TableErrorKind::WrongFieldCount(fields.len()), line, row, line_bol, start, last_start + last.len()));
+                continue;
+            }
+
+            // the first transition decides how many tapes the table has
+            let tapes = table.transitions.first().map(|transition| transition.tapes()).unwrap_or_else(|| entries(fields[1].1).len());
+            let error_count = errors.len();
+            let mut tuples = Vec::new();
+            for &(field_start, field) in &fields[1..4] {
+                let tuple = entries(field);
+                let kind = if tuple.len() != tapes {
+                    Some(TableErrorKind::WrongTapeCount { expected: tapes, found: tuple.len() })
+                } else if tuple.iter().any(|(_, entry)| entry.is_empty()) {
+                    Some(TableErrorKind::EmptySymbol)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    errors.push(self.create_error(kind, line, row, line_bol, field_start, field_start + field.len()));
+                }
+                tuples.push((field_start, tuple));
+            }
+            if errors.len() > error_count {
+                continue;
+            }
+
+            let (directions_start, direction_texts) = &tuples[2];
+            let mut directions = Vec::new();
+            for &(offset, text) in direction_texts {
+                match Direction::parse(text) {
+                    Some(direction) => directions.push(direction),
+                    None => {
+                        let kind = TableErrorKind::InvalidDirection(text.to_string());
+                        errors.push(self.create_error(kind, line, row, line_bol, directions_start + offset, directions_start + offset + text.len()));
+                    }
+                }
+            }
+            if errors.len() > error_count {
+                continue;
+            }
+
+            let (start, state) = fields[0];
+            let symbols = |index: usize| tuples[index].1.iter().map(|(_, symbol)| symbol.to_string()).collect();
+            let mut transition = Transition::multi(state, symbols(0), symbols(1), directions, fields[4].1);
+            transition.position = Some(Position { row, col: line[..start].chars().count() });
+            table.push(transition);
+        }
+
+        if errors.is_empty() {
+            Ok(table)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_transitions_around_comments() {
+        let table = TableReader::new("
+            // increments a binary number
+            # so does this comment
+            right 1 1 right right // trailing comment
+            right _ _ left  carry
+            carry 1 0 left carry //
+            carry _ 1 stay  done //the last one
+        ").read().unwrap();
+        assert_eq!(table.start, "right");
+        assert_eq!(table.transitions.len(), 4);
+        assert_eq!(table.transitions[0], Transition { position: Some(Position { row: 3, col: 12 }), ..Transition::new("right", "1", "1", Direction::Right, "right") });
+        assert_eq!(table.transitions[1].read, [BLANK]);
+        assert_eq!(table.transitions[2].directions, [Direction::Left]);
+        assert_eq!(table.transitions[3].directions, [Direction::Stay]);
+        assert_eq!(table.transitions[3].next, "done");
+    }
+
+    #[test]
+    fn reports_malformed_lines_with_their_positions() {
+        let errors = TableReader::new("q0 a b right\nq0 a b right q1\n  q1 b b up q2 // comment\nq2 a a left q3 # not a comment").read().unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].kind, TableErrorKind::WrongFieldCount(4));
+        assert_eq!((errors[0].position, errors[0].span), (Position { row: 0, col: 0 }, Span { start: 0, end: 12 }));
+        assert_eq!(errors[1].kind, TableErrorKind::InvalidDirection("up".to_string()));
+        assert_eq!(errors[1].position, Position { row: 2, col: 9 });
+        assert_eq!(errors[1].length, 2);
+        // only a line can start with #, later on it is just another field
+        assert_eq!(errors[2].kind, TableErrorKind::WrongFieldCount(9));
+        assert_eq!(errors[2].position, Position { row: 3, col: 0 });
+    }
+}
@@ -0,0 +1,165 @@
+use crate::diagnostics::Diagnostic;
+use crate::lexer_types::{Position, Span};
+use serde::Serialize;
+use std::fmt;
+
+pub const BLANK: &str = "_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Direction {
+    Left,
+    Right,
+    Stay
+}
+
+impl Direction {
+    pub fn parse(text: &str) -> Option<Direction> {
+        match text {
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            "stay" => Some(Direction::Stay),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction_str = match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Stay => "stay",
+        };
+        write!(f, "{}", direction_str)
+    }
+}
+
+// A transition of a k-tape machine reads, writes and moves one symbol per tape, every head moves on its own.
+// A single tape machine is the k = 1 case
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transition {
+    pub state : String,
+    pub read : Vec<String>,
+    pub write : Vec<String>,
+    pub directions : Vec<Direction>,
+    pub next : String,
+    pub position : Option<Position> //only known for tables read from source
+}
+
+impl Transition {
+    pub fn new(state: &str, read: &str, write: &str, direction: Direction, next: &str) -> Transition {
+        Transition::multi(state, vec![read.to_string()], vec![write.to_string()], vec![direction], next)
+    }
+
+    pub fn multi(state: &str, read: Vec<String>, write: Vec<String>, directions: Vec<Direction>, next: &str) -> Transition {
+        Transition {
+            state: state.to_string(),
+            read,
+            write,
+            directions,
+            next: next.to_string(),
+            position: None
+        }
+    }
+
+    pub fn tapes(&self) -> usize {
+        self.read.len()
+    }
+}
+
+// Tuples are written with commas, `q0 a,_ a,x right,right q1` on a 2-tape machine
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let directions: Vec<String> = self.directions.iter().map(|direction| direction.to_string()).collect();
+        write!(f, "{} {} {} {} {}", self.state, self.read.join(","), self.write.join(","), directions.join(","), self.next)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TransitionTable {
+    pub start : String,
+    pub transitions : Vec<Transition>
+}
+
+impl TransitionTable {
+    pub fn push(&mut self, transition: Transition) {
+        if self.transitions.is_empty() && self.start.is_empty() {
+            self.start = transition.state.clone();
+        }
+        self.transitions.push(transition);
+    }
+
+    // Every transition of a table uses the same number of tapes, an empty table has one
+    pub fn tapes(&self) -> usize {
+        self.transitions.first().map(|transition| transition.tapes()).unwrap_or(1)
+    }
+}
+
+// Writes the table back out in the README format
+// the start state's transitions come first so reading the output back keeps the same start state
+impl fmt::Display for TransitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (first, rest): (Vec<&Transition>, Vec<&Transition>) = self.transitions.iter().partition(|t| t.state == self.start);
+        for transition in first.into_iter().chain(rest) {
+            writeln!(f, "{}", transition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum TableErrorKind {
+    WrongFieldCount(usize),
+    InvalidDirection(String),
+    WrongTapeCount { expected: usize, found: usize },
+    EmptySymbol,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableError {
+    pub kind : TableErrorKind,
+    pub length : usize, //in chars
+    pub position : Position,
+    pub span : Span
+}
+
+impl TableErrorKind {
+    pub fn message(&self) -> String {
+        match self {
+            TableErrorKind::WrongFieldCount(count) => format!("expected 5 fields in a transition but found {}", count),
+            TableErrorKind::InvalidDirection(direction) => format!("invalid direction '{}'", direction),
+            TableErrorKind::WrongTapeCount { expected, found } => format!("expected {} tapes but found {}", expected, found),
+            TableErrorKind::EmptySymbol => "empty symbol in a tuple".to_string(),
+        }
+    }
+}
+
+impl TableError {
+    pub fn message(&self) -> String {
+        self.kind.message()
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(&self.message(), self.span);
+        match &self.kind {
+            TableErrorKind::WrongFieldCount(_) => diagnostic
+                .with_primary_label("malformed transition")
+                .with_note("transitions are written as <current-state> <read-symbol> <write-symbol> <move-direction> <next-state>, a multi-tape table separates the symbols and directions of its tapes with commas"),
+            TableErrorKind::InvalidDirection(_) => diagnostic
+                .with_primary_label("unknown direction")
+                .with_help("use 'left', 'right' or 'stay'"),
+            TableErrorKind::WrongTapeCount { expected, .. } => diagnostic
+                .with_primary_label("wrong number of tapes")
+                .with_note(&format!("every read, write and direction field of this table has {} comma separated entries", expected)),
+            TableErrorKind::EmptySymbol => diagnostic
+                .with_primary_label("nothing between the commas")
+                .with_help("use '_' for a blank"),
+        }
+    }
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at row {}, column {}", self.message(), self.position.row + 1, self.position.col + 1)
+    }
+}